@@ -1,149 +1,596 @@
 use std::collections::HashMap;
+use crate::builtins::Builtin;
 use crate::tokenizer::Token;
 
 #[derive(Debug, Clone)]
 pub enum Expr {
     Number(f64),
+    String(String),
     BinaryOperator {
         left: Box<Expr>,
         right: Box<Expr>,
         op: Token,
     },
+    UnaryOperator {
+        op: Token,
+        expr: Box<Expr>,
+    },
     Variable(String),
+    Vector(Vec<Expr>),
+    Map(Vec<(String, Expr)>),
+    Range {
+        start: Box<Expr>,
+        end: Box<Expr>,
+        step: Option<Box<Expr>>,
+        inclusive: bool,
+    },
+    Index(Box<Expr>, Box<Expr>),
+    Field(Box<Expr>, String),
     CodeBlock(Vec<Expr>),
-    Assign(String, Box<Expr>),
+    Let(String, Box<Expr>),
+    Assign {
+        target: Place,
+        op: Option<Token>, // The operator of a compound assignment like `+=`
+        value: Box<Expr>,
+    },
     Function(Vec<String>, Box<Expr>),
     FunctionCall(Box<Expr>, Vec<Expr>),
-    Switch(Vec<Expr>, Vec<Expr>),
+    MethodCall(Box<Expr>, String, Vec<Expr>), // `x.f(y)`: calls the field `f` of `x` if it has one, otherwise `f(x, y)`
+    Switch(Vec<Expr>, Vec<Expr>, Option<Box<Expr>>),
+    Match(Box<Expr>, Vec<(Pattern, Option<Expr>, Expr)>), // Arms are a pattern, an optional guard and a body
+    If(Box<Expr>, Box<Expr>, Option<Box<Expr>>),
     While(Box<Expr>, Box<Expr>),
+    For(String, Box<Expr>, Box<Expr>),
+    Break(Option<Box<Expr>>),
+    Continue,
+    Return(Option<Box<Expr>>),
+    Error, // Placeholder left by the parser where it recovered from a syntax error
+}
+
+// The target of an assignment: a variable, or an element or field reached from one.
+#[derive(Debug, Clone)]
+pub enum Place {
+    Variable(String),
+    Index(Box<Place>, Box<Expr>),
+    Field(Box<Place>, String),
+}
+
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Wildcard,
+    Binding(String),
+    Number(f64),
+    String(String),
+    Vector(Vec<Pattern>),
+    Rest(Option<String>), // `..` or `..name` inside a vector pattern
+}
+
+// One step from a variable towards the object a place refers to, with its index already evaluated.
+enum PathStep {
+    Index(Object),
+    Field(String),
 }
 
 #[derive(Debug, Clone)]
 pub enum Object {
     Number(f64),
+    String(String),
     Function {
         args: Vec<String>,
         body: Expr,
         context: Context
     },
     Vector(Vec<Object>),
+    Map(Vec<(String, Object)>), // Entries in insertion order
+    Builtin(Builtin),
+    // Ranges are kept as their bounds and only produce values while a loop walks them.
+    Range {
+        start: f64,
+        end: f64,
+        step: f64,
+        inclusive: bool,
+    },
     Null
 }
 
+// Variables live in a stack of scopes: `let` declares in the innermost one, assignment updates the nearest
+// declaration and lookups search from the innermost scope outwards.
 #[derive(Clone, Debug)]
 pub struct Context {
-    pub variables: HashMap<String, Object>,
+    scopes: Vec<HashMap<String, Object>>,
+}
+
+impl Context {
+    pub fn new() -> Context {
+        Context { scopes: vec![HashMap::new()] }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Object> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    pub fn declare(&mut self, name: String, value: Object) {
+        self.scopes.last_mut().expect("context has no scope").insert(name, value);
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Object> {
+        self.scopes.iter_mut().rev().find_map(|scope| scope.get_mut(name))
+    }
+
+    pub fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    pub fn pop_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    // All visible variables in a single map, inner declarations shadowing outer ones.
+    fn flatten(&self) -> HashMap<String, Object> {
+        let mut variables = HashMap::new();
+        for scope in &self.scopes {
+            variables.extend(scope.iter().map(|(name, value)| (name.clone(), value.clone())));
+        }
+        variables
+    }
+}
+
+impl Default for Context {
+    fn default() -> Context {
+        Context::new()
+    }
+}
+
+// Why evaluation left an expression early: an error, or a jump that unwinds to the enclosing loop or function call.
+enum Unwind {
+    Error(String),
+    Break(Object),
+    Continue,
+    Return(Object),
+}
+
+impl From<String> for Unwind {
+    fn from(message: String) -> Unwind {
+        Unwind::Error(message)
+    }
 }
 
 impl Expr {
     pub fn eval(&self, context: &mut Context) -> Result<Object, String> {
+        match self.eval_unwinding(context) {
+            Ok(value) => Ok(value),
+            Err(Unwind::Error(message)) => Err(message),
+            Err(Unwind::Break(_) | Unwind::Continue) => Err("`break` and `continue` can only be used inside a loop.".to_string()),
+            Err(Unwind::Return(_)) => Err("`return` can only be used inside a function.".to_string()),
+        }
+    }
+
+    fn eval_unwinding(&self, context: &mut Context) -> Result<Object, Unwind> {
         match self {
             Expr::Number(value) => Ok(Object::Number(*value)),
+            Expr::String(value) => Ok(Object::String(value.clone())),
             Expr::BinaryOperator { left, right, op } => eval_binary_operator(left, right, op, context),
-            Expr::Variable(name) => match context.variables.get(name) {
-                Some(value) => Ok(value.clone()),
-                None => Err(format!("Undefined variable: {}", name)),
+            Expr::UnaryOperator { op, expr } => eval_unary_operator(expr, op, context),
+            Expr::Variable(name) => lookup_variable(name, context),
+            Expr::Vector(elements) => {
+                let mut elements_eval = Vec::new();
+                for element in elements {
+                    elements_eval.push(element.eval_unwinding(context)?);
+                }
+                Ok(Object::Vector(elements_eval))
             },
-            Expr::CodeBlock(code) => {
-                let mut last_line_eval = Object::Null;
-                for line in code {
-                    last_line_eval = line.eval(context)?;
+            Expr::Map(entries) => {
+                let mut entries_eval: Vec<(String, Object)> = Vec::new();
+                for (key, value) in entries {
+                    let value = value.eval_unwinding(context)?;
+                    match entries_eval.iter_mut().find(|(name, _)| name == key) {
+                        Some(entry) => entry.1 = value,
+                        None => entries_eval.push((key.clone(), value)),
+                    }
+                }
+                Ok(Object::Map(entries_eval))
+            },
+            Expr::Range { start, end, step, inclusive } => {
+                let step = match step {
+                    Some(step) => step.eval_unwinding(context)?,
+                    None => Object::Number(1f64),
+                };
+                match (start.eval_unwinding(context)?, end.eval_unwinding(context)?, step) {
+                    (Object::Number(_), Object::Number(_), Object::Number(0f64)) => Err(Unwind::Error("Range step cannot be zero.".to_string())),
+                    (Object::Number(start), Object::Number(end), Object::Number(step)) => Ok(Object::Range { start, end, step, inclusive: *inclusive }),
+                    _ => Err(Unwind::Error("Range bounds and step must be numbers.".to_string())),
                 }
-                Ok(last_line_eval)
             },
-            Expr::Assign(name, value) => {
-                let value = value.eval(context)?;
-                context.variables.insert(name.clone(), value.clone());
+            Expr::Index(target, index) => {
+                if let Object::Vector(mut elements) = target.eval_unwinding(context)? {
+                    let index = resolve_index(index.eval_unwinding(context)?, elements.len())?;
+                    Ok(elements.swap_remove(index))
+                } else {
+                    Err(Unwind::Error("Only vectors can be indexed.".to_string()))
+                }
+            },
+            Expr::Field(target, name) => match target.eval_unwinding(context)? {
+                Object::Map(entries) => match entries.into_iter().find(|(key, _)| key == name) {
+                    Some((_, value)) => Ok(value),
+                    None => Err(Unwind::Error(format!("Map has no field: {}", name))),
+                },
+                _ => Err(Unwind::Error("Only maps have fields.".to_string())),
+            },
+            Expr::CodeBlock(code) => {
+                context.push_scope();
+                let result = eval_code_block(code, context);
+                context.pop_scope();
+                result
+            },
+            Expr::Let(name, value) => {
+                let value = value.eval_unwinding(context)?;
+                context.declare(name.clone(), value.clone());
                 Ok(value)
+            },
+            Expr::Assign { target, op, value } => {
+                let mut path = Vec::new();
+                let name = eval_place(target, &mut path, context)?;
+                let value = value.eval_unwinding(context)?;
+
+                let mut slot = match context.get_mut(name) {
+                    Some(slot) => slot,
+                    None => return Err(Unwind::Error(format!("Assignment to undeclared variable: {}", name))),
+                };
+                let length = path.len();
+                for (position, step) in path.into_iter().enumerate() {
+                    slot = match (slot, step) {
+                        (Object::Vector(elements), PathStep::Index(index)) => {
+                            let index = resolve_index(index, elements.len())?;
+                            &mut elements[index]
+                        },
+                        (Object::Map(entries), PathStep::Field(name)) => {
+                            // A plain `=` on the last field of the place adds the field if the map lacks it.
+                            let index = match entries.iter().position(|(key, _)| *key == name) {
+                                Some(index) => index,
+                                None if op.is_none() && position + 1 == length => {
+                                    entries.push((name, Object::Null));
+                                    entries.len() - 1
+                                },
+                                None => return Err(Unwind::Error(format!("Map has no field: {}", name))),
+                            };
+                            &mut entries[index].1
+                        },
+                        (_, PathStep::Index(_)) => return Err(Unwind::Error("Only vectors can be indexed.".to_string())),
+                        (_, PathStep::Field(_)) => return Err(Unwind::Error("Only maps have fields.".to_string())),
+                    };
+                }
+
+                *slot = match op {
+                    Some(op) => binary_operation(slot.clone(), value, op)?,
+                    None => value,
+                };
+                Ok(slot.clone())
             }
             Expr::Function(args, body) => {
                 Ok(Object::Function {args: args.clone(), body: *body.clone(), context: context.clone()})
             }
             Expr::FunctionCall(function, args) => {
-                if let Object::Function {args: func_args, body, context: func_context} = function.eval(context)? {
-                    let mut args_eval = Vec::new();
-                    let cloned_context = context.clone();
-                    let mut variables = cloned_context.variables.iter().chain(func_context.variables.iter()).collect::<HashMap<_,_>>();
-                    for arg in args {
-                        args_eval.push(arg.eval(context)?);
-                    }
-                    for (index, arg_eval) in args_eval.iter().enumerate() {
-                        variables.insert(&func_args[index], &arg_eval);
-
-                    }
-
-                    let new_variables: HashMap<String, Object> = variables.into_iter().map(|(str, obj)| (str.clone(), obj.clone())).collect();
-
-                    let result = body.eval(&mut Context {variables: new_variables})?;
-
-                    Ok(result)
-                } else {
-                    Err("".to_string())
+                let function = function.eval_unwinding(context)?;
+                let mut args_eval = Vec::new();
+                for arg in args {
+                    args_eval.push(arg.eval_unwinding(context)?);
                 }
+                call_function(function, args_eval, context)
+            },
+            Expr::MethodCall(receiver, name, args) => {
+                let receiver = receiver.eval_unwinding(context)?;
+                let field = match &receiver {
+                    Object::Map(entries) => entries.iter().find(|(key, _)| key == name).map(|(_, value)| value.clone()),
+                    _ => None,
+                };
 
-
-
-            }
-            Expr::Switch(cases, expressions) => {
+                let mut args_eval = Vec::new();
+                for arg in args {
+                    args_eval.push(arg.eval_unwinding(context)?);
+                }
+                match field {
+                    Some(function) => call_function(function, args_eval, context),
+                    None => {
+                        args_eval.insert(0, receiver);
+                        call_function(lookup_variable(name, context)?, args_eval, context)
+                    },
+                }
+            },
+            Expr::Switch(cases, expressions, default) => {
                 if cases.len() != expressions.len() {
-                    return Err("jfdjk".to_string());
+                    return Err(Unwind::Error("Switch has a different number of cases and expressions.".to_string()));
                 }
 
                 for (index, case) in cases.iter().enumerate() {
-                    let case_eval =  case.eval(context)?;
-                    match case_eval {
-                        Object::Number(value) => {
-                            if value != 0f64 {
-                                return Ok(expressions[index].eval(context)?)
-                            }
-                        },
-                        Object::Function {..} => return Ok(expressions[index].eval(context)?),
-
-                        _ => {}
+                    if object_to_bool(case.eval_unwinding(context)?) {
+                        return expressions[index].eval_unwinding(context);
                     }
                 }
 
-                Ok(Object::Null)
+                match default {
+                    Some(expr) => expr.eval_unwinding(context),
+                    None => Ok(Object::Null),
+                }
+            },
+            Expr::Match(value, arms) => {
+                let value = value.eval_unwinding(context)?;
+                for (pattern, guard, expr) in arms {
+                    let mut bindings = Vec::new();
+                    if !match_pattern(pattern, &value, &mut bindings) {
+                        continue;
+                    }
+
+                    context.push_scope();
+                    for (name, binding) in bindings {
+                        context.declare(name, binding);
+                    }
+                    let result = eval_match_arm(guard, expr, context);
+                    context.pop_scope();
+                    if let Some(result) = result? {
+                        return Ok(result);
+                    }
+                }
+                Err(Unwind::Error("No pattern matches the value.".to_string()))
+            },
+            Expr::If(condition, then_expr, else_expr) => {
+                if object_to_bool(condition.eval_unwinding(context)?) {
+                    then_expr.eval_unwinding(context)
+                } else if let Some(else_expr) = else_expr {
+                    else_expr.eval_unwinding(context)
+                } else {
+                    Ok(Object::Null)
+                }
             },
             Expr::While(condition, expr) => {
-                while object_to_bool(condition.eval(context)?) {
-                    expr.eval(context)?;
+                while object_to_bool(condition.eval_unwinding(context)?) {
+                    if let Some(value) = loop_control(expr.eval_unwinding(context))? {
+                        return Ok(value);
+                    }
                 }
                 Ok(Object::Null)
+            },
+            Expr::For(name, iterable, expr) => {
+                match iterable.eval_unwinding(context)? {
+                    Object::Vector(elements) => {
+                        for element in elements {
+                            if let Some(value) = loop_control(eval_loop_body(name, element, expr, context))? {
+                                return Ok(value);
+                            }
+                        }
+                    },
+                    Object::Range { start, end, step, inclusive } => {
+                        // Values are computed from the iteration count so float steps do not accumulate error.
+                        let mut count = 0f64;
+                        loop {
+                            let value = start + count * step;
+                            let in_range = if step > 0f64 { value < end } else { value > end };
+                            if !(in_range || inclusive && value == end) {
+                                break;
+                            }
+                            if let Some(value) = loop_control(eval_loop_body(name, Object::Number(value), expr, context))? {
+                                return Ok(value);
+                            }
+                            count += 1f64;
+                        }
+                    },
+                    _ => return Err(Unwind::Error("Only vectors and ranges can be iterated.".to_string())),
+                }
+                Ok(Object::Null)
+            },
+            Expr::Break(value) => Err(Unwind::Break(eval_jump_value(value, context)?)),
+            Expr::Continue => Err(Unwind::Continue),
+            Expr::Return(value) => Err(Unwind::Return(eval_jump_value(value, context)?)),
+            Expr::Error => Err(Unwind::Error("Cannot evaluate code that contains syntax errors.".to_string())),
+        }
+    }
+}
+
+// Collects the variables the pattern binds while checking whether `value` matches it.
+fn match_pattern(pattern: &Pattern, value: &Object, bindings: &mut Vec<(String, Object)>) -> bool {
+    match (pattern, value) {
+        (Pattern::Wildcard, _) => true,
+        (Pattern::Binding(name), _) => {
+            bindings.push((name.clone(), value.clone()));
+            true
+        },
+        (Pattern::Number(expected), Object::Number(value)) => expected == value,
+        (Pattern::String(expected), Object::String(value)) => expected == value,
+        (Pattern::Vector(items), Object::Vector(elements)) => {
+            let Some(rest) = items.iter().position(|item| matches!(item, Pattern::Rest(_))) else {
+                return items.len() == elements.len()
+                    && items.iter().zip(elements).all(|(item, element)| match_pattern(item, element, bindings));
+            };
+
+            // Items before the rest match from the front, the ones after it from the back.
+            let suffix = items.len() - rest - 1;
+            if elements.len() < rest + suffix {
+                return false;
             }
+            let middle = rest..elements.len() - suffix;
+            if let Pattern::Rest(Some(name)) = &items[rest] {
+                bindings.push((name.clone(), Object::Vector(elements[middle.clone()].to_vec())));
+            }
+            items[..rest].iter().zip(&elements[..rest]).all(|(item, element)| match_pattern(item, element, bindings))
+                && items[rest + 1..].iter().zip(&elements[middle.end..]).all(|(item, element)| match_pattern(item, element, bindings))
+        },
+        _ => false,
+    }
+}
+
+// Runs an arm whose pattern matched; `None` means its guard rejected the value.
+fn eval_match_arm(guard: &Option<Expr>, expr: &Expr, context: &mut Context) -> Result<Option<Object>, Unwind> {
+    if let Some(guard) = guard {
+        if !object_to_bool(guard.eval_unwinding(context)?) {
+            return Ok(None);
         }
     }
+    Ok(Some(expr.eval_unwinding(context)?))
+}
+
+// Evaluates the path of a place from the outermost step in, returning the variable the place starts at.
+fn eval_place<'a>(place: &'a Place, path: &mut Vec<PathStep>, context: &mut Context) -> Result<&'a str, Unwind> {
+    match place {
+        Place::Variable(name) => Ok(name),
+        Place::Index(target, index) => {
+            let name = eval_place(target, path, context)?;
+            path.push(PathStep::Index(index.eval_unwinding(context)?));
+            Ok(name)
+        },
+        Place::Field(target, field) => {
+            let name = eval_place(target, path, context)?;
+            path.push(PathStep::Field(field.clone()));
+            Ok(name)
+        },
+    }
+}
+
+fn lookup_variable(name: &str, context: &Context) -> Result<Object, Unwind> {
+    match (context.get(name), Builtin::from_name(name)) {
+        (Some(value), _) => Ok(value.clone()),
+        (None, Some(builtin)) => Ok(Object::Builtin(builtin)),
+        (None, None) => Err(Unwind::Error(format!("Undefined variable: {}", name))),
+    }
+}
+
+fn call_function(function: Object, args: Vec<Object>, context: &Context) -> Result<Object, Unwind> {
+    match function {
+        Object::Builtin(builtin) => Ok(builtin.call(args)?),
+        Object::Function {args: func_args, body, context: func_context} => {
+            if args.len() != func_args.len() {
+                return Err(Unwind::Error(format!("Function expects {} arguments but got {}.", func_args.len(), args.len())));
+            }
+
+            let mut variables = context.flatten();
+            variables.extend(func_context.flatten());
+
+            // The body runs in its own scope on top of the caller's and the closure's variables.
+            let mut call_context = Context { scopes: vec![variables] };
+            call_context.push_scope();
+            for (name, arg) in func_args.iter().zip(args) {
+                call_context.declare(name.clone(), arg);
+            }
+
+            match body.eval_unwinding(&mut call_context) {
+                Err(Unwind::Return(value)) => Ok(value),
+                result => result,
+            }
+        },
+        _ => Err(Unwind::Error("Only functions can be called.".to_string())),
+    }
+}
+
+fn eval_code_block(code: &[Expr], context: &mut Context) -> Result<Object, Unwind> {
+    let mut last_line_eval = Object::Null;
+    for line in code {
+        last_line_eval = line.eval_unwinding(context)?;
+    }
+    Ok(last_line_eval)
+}
+
+// Runs one iteration of a `for` loop with the loop variable declared in a scope of its own.
+fn eval_loop_body(name: &str, value: Object, expr: &Expr, context: &mut Context) -> Result<Object, Unwind> {
+    context.push_scope();
+    context.declare(name.to_string(), value);
+    let result = expr.eval_unwinding(context);
+    context.pop_scope();
+    result
+}
+
+// Handles `break` and `continue` after one iteration of a loop body; `Some` carries the value the loop ends with.
+fn loop_control(result: Result<Object, Unwind>) -> Result<Option<Object>, Unwind> {
+    match result {
+        Ok(_) | Err(Unwind::Continue) => Ok(None),
+        Err(Unwind::Break(value)) => Ok(Some(value)),
+        Err(unwind) => Err(unwind),
+    }
+}
+
+fn eval_jump_value(value: &Option<Box<Expr>>, context: &mut Context) -> Result<Object, Unwind> {
+    match value {
+        Some(value) => value.eval_unwinding(context),
+        None => Ok(Object::Null),
+    }
+}
+
+// Turns an index object into a position in a vector of length `len`; negative indices count from the end.
+fn resolve_index(index: Object, len: usize) -> Result<usize, String> {
+    let index = match index {
+        Object::Number(value) if value.fract() == 0f64 => value,
+        Object::Number(value) => return Err(format!("Index {} is not an integer.", value)),
+        _ => return Err("Index is not a number.".to_string()),
+    };
+
+    let position = if index < 0f64 { index + len as f64 } else { index };
+    if position < 0f64 || position >= len as f64 {
+        return Err(format!("Index {} is out of range for a vector of length {}.", index, len));
+    }
+    Ok(position as usize)
 }
 
 fn object_to_bool(object: Object) -> bool {
     match object {
         Object::Null => false,
         Object::Number(value) => value != 0f64,
+        Object::String(value) => !value.is_empty(),
         Object::Function {..} => true,
-        Object::Vector(..) => true
-    }
-}
-
-fn eval_binary_operator(left: &Box<Expr>, right: &Box<Expr>, op: &Token, context: &mut Context) -> Result<Object, String> {
-    if let Object::Number(left_eval) = left.eval(context)? {
-        if let Object::Number(right_eval) = right.eval(context)? {
-            Ok(Object::Number(match op {
-                Token::Plus => left_eval + right_eval,
-                Token::Minus => left_eval - right_eval,
-                Token::Mul => left_eval * right_eval,
-                Token::Div => left_eval / right_eval,
-                Token::Mod => left_eval % right_eval,
-                Token::EqualEqual => if left_eval == right_eval {1f64} else {0f64},
-                Token::Less => if left_eval < right_eval {1f64} else {0f64},
-                Token::Greater => if left_eval > right_eval {1f64} else {0f64},
-                Token::LessEqual => if left_eval <= right_eval {1f64} else {0f64},
-                Token::GreaterEqual => if left_eval >= right_eval {1f64} else {0f64},
-                _ => 0f64
-            }))
-        } else {
-            Err("Right operand is not a number.".to_string())
-        }
-    } else {
-        Err("Left operand is not a number.".to_string())
+        Object::Vector(..) => true,
+        Object::Map(..) => true,
+        Object::Builtin(..) => true,
+        Object::Range {..} => true
+    }
+}
+
+fn bool_to_object(value: bool) -> Object {
+    Object::Number(if value {1f64} else {0f64})
+}
+
+fn eval_unary_operator(expr: &Expr, op: &Token, context: &mut Context) -> Result<Object, Unwind> {
+    match (op, expr.eval_unwinding(context)?) {
+        (Token::Bang, value) => Ok(bool_to_object(!object_to_bool(value))),
+        (Token::Minus, Object::Number(value)) => Ok(Object::Number(-value)),
+        (Token::Plus, Object::Number(value)) => Ok(Object::Number(value)),
+        _ => Err(Unwind::Error(format!("Operand of unary {} is not a number.", op))),
+    }
+}
+
+fn eval_binary_operator(left: &Expr, right: &Expr, op: &Token, context: &mut Context) -> Result<Object, Unwind> {
+    if let Token::AndAnd | Token::OrOr = op {
+        // The right side only runs when the left side does not already decide the result.
+        let left_eval = object_to_bool(left.eval_unwinding(context)?);
+        let result = if left_eval == (*op == Token::OrOr) { left_eval } else { object_to_bool(right.eval_unwinding(context)?) };
+        return Ok(bool_to_object(result));
+    }
+
+    let left_eval = left.eval_unwinding(context)?;
+    let right_eval = right.eval_unwinding(context)?;
+    Ok(binary_operation(left_eval, right_eval, op)?)
+}
+
+fn binary_operation(left: Object, right: Object, op: &Token) -> Result<Object, String> {
+    match (left, right) {
+        (Object::Number(left_eval), Object::Number(right_eval)) => match op {
+            Token::Plus => Ok(Object::Number(left_eval + right_eval)),
+            Token::Minus => Ok(Object::Number(left_eval - right_eval)),
+            Token::Mul => Ok(Object::Number(left_eval * right_eval)),
+            Token::Div => Ok(Object::Number(left_eval / right_eval)),
+            Token::Mod => Ok(Object::Number(left_eval % right_eval)),
+            Token::Caret => Ok(Object::Number(left_eval.powf(right_eval))),
+            Token::EqualEqual => Ok(bool_to_object(left_eval == right_eval)),
+            Token::BangEqual => Ok(bool_to_object(left_eval != right_eval)),
+            Token::Less => Ok(bool_to_object(left_eval < right_eval)),
+            Token::Greater => Ok(bool_to_object(left_eval > right_eval)),
+            Token::LessEqual => Ok(bool_to_object(left_eval <= right_eval)),
+            Token::GreaterEqual => Ok(bool_to_object(left_eval >= right_eval)),
+            _ => Err(format!("Operator {} is not supported for numbers.", op)),
+        },
+        (Object::String(left_eval), Object::String(right_eval)) => match op {
+            Token::Plus => Ok(Object::String(left_eval + &right_eval)),
+            Token::EqualEqual => Ok(bool_to_object(left_eval == right_eval)),
+            Token::BangEqual => Ok(bool_to_object(left_eval != right_eval)),
+            _ => Err(format!("Operator {} is not supported for strings.", op)),
+        },
+        (Object::Number(_), _) => Err("Right operand is not a number.".to_string()),
+        (Object::String(_), _) => Err("Right operand is not a string.".to_string()),
+        _ => Err("Left operand is not a number.".to_string()),
     }
 }
@@ -0,0 +1,51 @@
+use crate::ast::Object;
+
+// Functions that are always available. They are looked up only when no variable of the same name is in scope.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Builtin {
+    Keys,
+    Values,
+    Has,
+    Remove,
+}
+
+impl Builtin {
+    pub fn from_name(name: &str) -> Option<Builtin> {
+        match name {
+            "keys" => Some(Builtin::Keys),
+            "values" => Some(Builtin::Values),
+            "has" => Some(Builtin::Has),
+            "remove" => Some(Builtin::Remove),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Builtin::Keys => "keys",
+            Builtin::Values => "values",
+            Builtin::Has => "has",
+            Builtin::Remove => "remove",
+        }
+    }
+
+    pub fn call(&self, args: Vec<Object>) -> Result<Object, String> {
+        match (self, &args[..]) {
+            (Builtin::Keys, [Object::Map(entries)]) => {
+                Ok(Object::Vector(entries.iter().map(|(key, _)| Object::String(key.clone())).collect()))
+            },
+            (Builtin::Values, [Object::Map(entries)]) => {
+                Ok(Object::Vector(entries.iter().map(|(_, value)| value.clone()).collect()))
+            },
+            (Builtin::Has, [Object::Map(entries), Object::String(key)]) => {
+                Ok(Object::Number(if entries.iter().any(|(name, _)| name == key) {1f64} else {0f64}))
+            },
+            // Values are copied when they are read, so this returns a new map rather than changing its argument.
+            (Builtin::Remove, [Object::Map(entries), Object::String(key)]) => {
+                Ok(Object::Map(entries.iter().filter(|(name, _)| name != key).cloned().collect()))
+            },
+            (Builtin::Keys | Builtin::Values, _) => Err(format!("{}() expects a map.", self.name())),
+            (Builtin::Has | Builtin::Remove, _) => Err(format!("{}() expects a map and a string key.", self.name())),
+        }
+    }
+}
@@ -0,0 +1,24 @@
+use crate::tokenizer::Span;
+
+// Formats `message` rustc-style: a location header, the source line the span starts on and carets under the
+// spanned text. Line and column are recomputed from the byte offsets so they always agree with `source`.
+pub fn render(source: &str, span: Span, message: &str) -> String {
+    let start = span.start.min(source.len());
+    let line_start = source[..start].rfind('\n').map_or(0, |index| index + 1);
+    let line_end = source[start..].find('\n').map_or(source.len(), |index| start + index);
+    let line = source[line_start..line_end].trim_end_matches('\r');
+    let line_number = source[..line_start].matches('\n').count() + 1;
+
+    // Keep tabs from the source line in the padding so the carets stay aligned with it.
+    let padding: String = source[line_start..start].chars().map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+    let end = span.end.clamp(start, line_start + line.len());
+    let width = source[start..end].chars().count().max(1);
+
+    let column = source[line_start..start].chars().count() + 1;
+    let gutter = " ".repeat(line_number.to_string().len());
+
+    format!(
+        "error: {}\n{}--> {}:{}\n{} |\n{} | {}\n{} | {}{}\n",
+        message, gutter, line_number, column, gutter, line_number, line, gutter, padding, "^".repeat(width)
+    )
+}
@@ -1,3 +1,5 @@
 pub mod tokenizer;
 pub mod ast;
-pub mod parser;
\ No newline at end of file
+pub mod builtins;
+pub mod parser;
+pub mod diagnostic;
\ No newline at end of file
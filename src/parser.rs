@@ -1,173 +1,633 @@
-use crate::ast::{Expr};
-use crate::tokenizer::Token;
+use std::fmt;
+use crate::ast::{Expr, Pattern, Place};
+use crate::diagnostic;
+use crate::tokenizer::{Span, SpannedToken, Token};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{Context, Object};
+    use crate::tokenizer::Tokenizer;
+
+    fn parse_error(code: &str) -> ParseError {
+        let tokens = Tokenizer::new(code).tokenize().unwrap();
+        Parser::new(&tokens).start_parsing().unwrap_err()
+    }
+
+    #[test]
+    fn test_expected_tokens() {
+        assert_eq!(parse_error("f(1 2)").to_string(), "expected `,` or `)`, found number `2`");
+        assert_eq!(parse_error("fn a b -> a").to_string(), "expected `,` or `->`, found identifier `b`");
+        assert_eq!(parse_error("{ 1 2 }").to_string(), "expected `->`, found number `2`");
+        assert_eq!(parse_error("[a; b").to_string(), "expected `;` or `]`, found end of input");
+        assert_eq!(parse_error("(1 + )").to_string(), "expected expression, found `)`");
+    }
+
+    #[test]
+    fn test_error_span() {
+        let error = parse_error("[x = 1;\n y = (2 + 3]");
+        assert_eq!(error, ParseError::UnexpectedToken {
+            found: Token::RBracket,
+            span: Span { start: 19, end: 20, line: 2, column: 12, end_line: 2, end_column: 13 },
+            expected: vec!["`)`"],
+        });
+    }
+
+    #[test]
+    fn test_trailing_tokens() {
+        let code = "[a = 1] garbage 2";
+        let tokens = Tokenizer::new(code).tokenize().unwrap();
+        let error = Parser::new(&tokens).parse_program().unwrap_err();
+        assert_eq!(error.to_string(), "unexpected identifier `garbage` after the end of the program");
+        assert!(error.render(code).ends_with("1 | [a = 1] garbage 2\n  |         ^^^^^^^^^\n"));
+
+        let tokens = Tokenizer::new("[a = 1; a]").tokenize().unwrap();
+        assert!(Parser::new(&tokens).parse_program().is_ok());
+    }
+
+    #[test]
+    fn test_recovery() {
+        let tokens = Tokenizer::new("[a = (1 +; b = 2; f(1 2, 3); { c 1, d -> 4 }; e = ]").tokenize().unwrap();
+        let (program, errors) = Parser::new(&tokens).parse_recovering();
+        let messages: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+        assert_eq!(messages, vec![
+            "expected expression, found `;`",
+            "expected `,` or `)`, found number `2`",
+            "expected `->`, found number `1`",
+            "expected expression, found `]`",
+        ]);
+
+        let Expr::CodeBlock(lines) = program else { panic!("expected a code block, got {:?}", program) };
+        assert_eq!(lines.len(), 5);
+        assert!(matches!(lines[0], Expr::Error));
+        assert!(matches!(lines[1], Expr::Assign { .. }));
+        assert!(matches!(&lines[2], Expr::FunctionCall(_, args) if matches!(args[..], [Expr::Number(_), Expr::Number(_)])));
+        assert!(matches!(&lines[3], Expr::Switch(cases, _, _) if matches!(cases[..], [Expr::Error, Expr::Variable(_)])));
+        assert!(matches!(lines[4], Expr::Error));
+    }
+
+    #[test]
+    fn test_recovery_foreign_separator() {
+        let tokens = Tokenizer::new("[a; b, c; d]").tokenize().unwrap();
+        let (program, errors) = Parser::new(&tokens).parse_recovering();
+        let messages: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+        assert_eq!(messages, vec!["expected `;` or `]`, found `,`"]);
+
+        let Expr::CodeBlock(lines) = program else { panic!("expected a code block, got {:?}", program) };
+        assert_eq!(lines.len(), 3);
+        assert!(matches!(&lines[2], Expr::Variable(name) if name == "d"));
+
+        let tokens = Tokenizer::new("f(x, [a; b, c])").tokenize().unwrap();
+        let (program, errors) = Parser::new(&tokens).parse_recovering();
+        assert_eq!(errors[0].to_string(), "expected `;` or `]`, found `,`");
+        assert!(matches!(&program, Expr::FunctionCall(_, args) if matches!(&args[2], Expr::Variable(name) if name == "c")));
+    }
+
+    #[test]
+    fn test_recovery_missing_closer() {
+        let tokens = Tokenizer::new("[a; (b c] d").tokenize().unwrap();
+        let (_, errors) = Parser::new(&tokens).parse_recovering();
+        let messages: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+        assert_eq!(messages, vec![
+            "expected `)`, found identifier `c`",
+            "unexpected identifier `d` after the end of the program",
+        ]);
+    }
+
+    fn eval(code: &str) -> Object {
+        let tokens = Tokenizer::new(code).tokenize().unwrap();
+        let program = Parser::new(&tokens).parse_program().unwrap();
+        program.eval(&mut Context::new()).unwrap()
+    }
+
+    fn eval_number(code: &str) -> f64 {
+        match eval(code) {
+            Object::Number(value) => value,
+            other => panic!("expected a number, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_precedence_and_associativity() {
+        assert_eq!(eval_number("1 - 2 - 3"), -4.0);
+        assert_eq!(eval_number("2 ^ 3 ^ 2"), 512.0);
+        assert_eq!(eval_number("2 * 3 ^ 2"), 18.0);
+        assert_eq!(eval_number("1 + 2 * 3 % 4"), 3.0);
+        assert_eq!(eval_number("1 + 2 * 3 < 8"), 1.0);
+        assert_eq!(eval_number("[let f = fn x -> x * 2; f(3) ^ 2]"), 36.0);
+    }
+
+    #[test]
+    fn test_unary_operators() {
+        assert_eq!(eval_number("-5"), -5.0);
+        assert_eq!(eval_number("[let x = 4; -x]"), -4.0);
+        assert_eq!(eval_number("-(1 + 2)"), -3.0);
+        assert_eq!(eval_number("-2 ^ 2"), -4.0);
+        assert_eq!(eval_number("2 ^ -1"), 0.5);
+        assert_eq!(eval_number("2 * -3 - -1"), -5.0);
+        assert_eq!(eval_number("+7"), 7.0);
+        assert_eq!(eval_number("!0 + !5 + !!5"), 2.0);
+        assert_eq!(eval_number("!\"\""), 1.0);
+    }
+
+    #[test]
+    fn test_logical_operators() {
+        assert_eq!(eval_number("1 != 2"), 1.0);
+        assert_eq!(eval_number("2 != 2"), 0.0);
+        assert_eq!(eval_number("1 < 2 && 2 < 3"), 1.0);
+        assert_eq!(eval_number("1 || 0 && 0"), 1.0);
+        assert_eq!(eval_number("0 || \"\""), 0.0);
+        assert_eq!(eval_number("[let x = 0; 0 && (x = 1); 1 || (x = 2); x]"), 0.0);
+        assert_eq!(eval_number("[let x = 0; 1 && (x = 1); 0 || (x = x + 2); x]"), 3.0);
+    }
+
+    fn eval_error(code: &str) -> String {
+        let tokens = Tokenizer::new(code).tokenize().unwrap();
+        let program = Parser::new(&tokens).parse_program().unwrap();
+        program.eval(&mut Context::new()).unwrap_err()
+    }
+
+    #[test]
+    fn test_string_operators() {
+        assert!(matches!(eval("\"ab\" + \"c\""), Object::String(value) if value == "abc"));
+        assert_eq!(eval_number("\"a\" == \"a\""), 1.0);
+        assert_eq!(eval_number("\"a\" == \"b\""), 0.0);
+        assert_eq!(eval_number("\"a\" != \"b\""), 1.0);
+        assert_eq!(eval_error("\"a\" - \"b\""), "Operator `-` is not supported for strings.");
+    }
+
+    #[test]
+    fn test_unsupported_operators() {
+        let expr = Expr::BinaryOperator { left: Box::new(Expr::Number(1.0)), right: Box::new(Expr::Number(2.0)), op: Token::Comma };
+        assert_eq!(expr.eval(&mut Context::new()).unwrap_err(), "Operator `,` is not supported for numbers.");
+    }
+
+    #[test]
+    fn test_vectors() {
+        assert!(matches!(eval("[]"), Object::Vector(elements) if elements.is_empty()));
+        assert!(matches!(eval("[1 + 1,]"), Object::Vector(elements) if matches!(elements[..], [Object::Number(2.0)])));
+        assert!(matches!(eval("[1 + 1]"), Object::Number(2.0)));
+        assert_eq!(eval_number("[let v = [10, 20, [30, 40],]; v[1] + v[2][-1] + v[-3]]"), 70.0);
+        assert_eq!(eval_number("[let f = fn -> [1, 2, 3]; f()[2]]"), 3.0);
+        assert_eq!(eval_number("[let i = 0; let s = 0; let v = [1, 2, 3]; while i < 3 [s = s + v[i]; i = i + 1]; s]"), 6.0);
+        assert_eq!(eval_number("[let v = [1, 2]; let i = 0; while (v[i] < 2) [i = i + 1]; i]"), 1.0);
+    }
+
+    #[test]
+    fn test_indexing_in_loop_heads() {
+        let message = "`[` after a loop head starts the loop body; put the head in parentheses to index it";
+        assert_eq!(parse_error("[let v = [1,2,3]; while v[0] < 3 [v[0] += 1]; v[0]]").to_string(), message);
+        assert_eq!(parse_error("for x in v[1] [x]").to_string(), message);
+        assert_eq!(eval_number("[let v = [1,2,3]; while (v[0] < 3) [v[0] += 1]; v[0]]"), 3.0);
+        assert_eq!(eval_number("[let v = [1, 2]; let s = 0; for x in v [s += x]; s]"), 3.0);
+    }
+
+    #[test]
+    fn test_index_errors() {
+        assert_eq!(eval_error("[1, 2][2]"), "Index 2 is out of range for a vector of length 2.");
+        assert_eq!(eval_error("[1, 2][-3]"), "Index -3 is out of range for a vector of length 2.");
+        assert_eq!(eval_error("[1, 2][0.5]"), "Index 0.5 is not an integer.");
+        assert_eq!(eval_error("5[0]"), "Only vectors can be indexed.");
+    }
+
+    #[test]
+    fn test_if_else() {
+        assert_eq!(eval_number("if 1 < 2 then 10 else 20"), 10.0);
+        assert_eq!(eval_number("if 0 then 10 else if 1 then 20 else 30"), 20.0);
+        assert_eq!(eval_number("[let x = 5; if x > 3 then [x = x * 2; x] else 0] + 1"), 11.0);
+        assert!(matches!(eval("if \"\" then 1"), Object::Null));
+        assert_eq!(parse_error("if 1 2").to_string(), "expected `then`, found number `2`");
+    }
+
+    #[test]
+    fn test_switch_default_and_truthiness() {
+        assert_eq!(eval_number("{ 0 -> 1, 0 -> 2, _ -> 3 }"), 3.0);
+        assert_eq!(eval_number("{ 0 -> 1, 5 -> 2, _ -> 3, }"), 2.0);
+        assert!(matches!(eval("{ 0 -> 1 }"), Object::Null));
+        assert_eq!(eval_number("{ [] -> 1, _ -> 2 }"), 1.0);
+        assert_eq!(eval_number("{ \"text\" -> 1, _ -> 2 }"), 1.0);
+        assert_eq!(eval_number("if [1,] then 1 else 2"), 1.0);
+        assert_eq!(parse_error("{ _ -> 1, 1 -> 2 }").to_string(), "the `_` arm must be the last arm of a switch");
+    }
+
+    #[test]
+    fn test_let_scoping() {
+        assert_eq!(eval_number("[let x = 1; [let x = 2; x = x + 1]; x]"), 1.0);
+        assert_eq!(eval_number("[let x = 1; [x = 5]; x]"), 5.0);
+        assert_eq!(eval_error("[let f = fn a, b -> a + b; f(1, 2, 3)]"), "Function expects 2 arguments but got 3.");
+        assert_eq!(eval_error("[let b = 100; let f = fn a, b -> a + b; f(1)]"), "Function expects 2 arguments but got 1.");
+        assert_eq!(eval_number("[let f = fn n -> [let y = n * 2; y]; f(3) + f(4)]"), 14.0);
+        assert_eq!(eval_error("[[let y = 1]; y]"), "Undefined variable: y");
+        assert_eq!(eval_error("[let count = 0; cuont = 1]"), "Assignment to undeclared variable: cuont");
+        assert_eq!(parse_error("let 1 = 2").to_string(), "expected identifier, found number `1`");
+        assert_eq!(parse_error("let x 2").to_string(), "expected `=`, found number `2`");
+    }
+
+    #[test]
+    fn test_for_loops() {
+        assert_eq!(eval_number("[let s = 0; for x in [1, 2, 3] [s = s + x]; s]"), 6.0);
+        assert_eq!(eval_number("[let s = 0; for i in 0..5 [s = s + i]; s]"), 10.0);
+        assert_eq!(eval_number("[let s = 0; for i in 1..=5 [s = s + i]; s]"), 15.0);
+        assert_eq!(eval_number("[let s = 0; for i in 0..10 step 3 [s = s + i]; s]"), 18.0);
+        assert_eq!(eval_number("[let step = 2; let s = 0; for i in 0..10 step step + 1 [s = s + i]; s + step]"), 20.0);
+        assert_eq!(eval_number("[let s = 0; for i in 5..=1 step -2 [s = s * 10 + i]; s]"), 531.0);
+        assert_eq!(eval_number("[let n = 0; for i in 0..=1 step 0.25 [n = n + 1]; n]"), 5.0);
+        assert_eq!(eval_number("[let s = 0; for i in 3..1 [s = s + 1]; s]"), 0.0);
+        assert_eq!(eval_number("[let s = 0; let v = [1, 2]; for x in v [s = s + x]; s]"), 3.0);
+        assert!(matches!(eval("0..1000000000"), Object::Range { start, end, step, inclusive: false } if start == 0.0 && end == 1e9 && step == 1.0));
+        assert_eq!(eval_error("[for i in 0..3 [let x = i]; x]"), "Undefined variable: x");
+        assert_eq!(eval_error("for i in 0..3 step 0 i"), "Range step cannot be zero.");
+        assert_eq!(eval_error("for c in \"abc\" c"), "Only vectors and ranges can be iterated.");
+        assert_eq!(parse_error("for 1 in v x").to_string(), "expected identifier, found number `1`");
+        assert_eq!(parse_error("for x v x").to_string(), "expected `in`, found identifier `v`");
+    }
+
+    #[test]
+    fn test_break_continue_return() {
+        assert_eq!(eval_number("[let i = 0; while 1 [i = i + 1; if i == 5 then break i * 10]]"), 50.0);
+        assert!(matches!(eval("for i in 0..3 [break]"), Object::Null));
+        assert_eq!(eval_number("[let s = 0; for i in 0..10 [if i % 2 == 0 then continue; s = s + i]; s]"), 25.0);
+        assert_eq!(eval_number("[let s = 0; for i in 0..10 [{ i > 3 -> break, _ -> s = s + i }]; s]"), 6.0);
+        assert_eq!(eval_number("[let n = 0; for i in 0..3 [for j in 0..3 [if j == 1 then break; n = n + 1]]; n]"), 3.0);
+        assert_eq!(eval_number("[let f = fn v -> [for x in v [if x > 2 then return x]; 0]; f([1, 5, 3]) + f([1,])]"), 5.0);
+        assert_eq!(eval_number("[let f = fn -> [let i = 0; while 1 [i = i + 1; if i == 3 then return i]]; f() + f()]"), 6.0);
+        assert!(matches!(eval("[let f = fn -> [return; 1]; f()]"), Object::Null));
+        assert_eq!(eval_error("[let x = 1; for i in 0..1 [let y = x; break]; y]"), "Undefined variable: y");
+        assert_eq!(parse_error("[1; break]").to_string(), "`break` can only be used inside a loop");
+        assert_eq!(parse_error("while 1 fn -> continue").to_string(), "`continue` can only be used inside a loop");
+        assert_eq!(parse_error("return 1").to_string(), "`return` can only be used inside a function");
+    }
+
+    #[test]
+    fn test_assignment_targets() {
+        assert_eq!(eval_number("[let x = 10; x += 5; x -= 3; x *= 2; x /= 4; x %= 4; x]"), 2.0);
+        assert_eq!(eval_number("[let a = 1; let b = 2; a = b = 7; a + b]"), 14.0);
+        assert_eq!(eval_number("[let v = [1, 2, 3]; v[0] = 10; v[-1] += 5; v[0] + v[1] + v[2]]"), 20.0);
+        assert_eq!(eval_number("[let m = [[1, 2], [3, 4]]; m[1][0] *= 10; m[1][0]]"), 30.0);
+        assert_eq!(eval_number("[let v = [1, 2]; let w = v; v[0] = 5; w[0]]"), 1.0);
+        assert_eq!(eval_number("[let v = [0, 0]; let i = 0; v[i = 1] = 4; v[1] + i]"), 5.0);
+        assert!(matches!(eval("[let s = \"a\"; s += \"b\"; s]"), Object::String(value) if value == "ab"));
+        assert_eq!(eval_error("[let v = [1, 2]; v[2] = 0]"), "Index 2 is out of range for a vector of length 2.");
+        assert_eq!(eval_error("[let x = 1; x[0] = 2]"), "Only vectors can be indexed.");
+        assert_eq!(eval_error("[y += 1]"), "Assignment to undeclared variable: y");
+        assert_eq!(parse_error("1 + x = 2").to_string(), "invalid left-hand side of `=`");
+        assert_eq!(parse_error("f() += 1").to_string(), "invalid left-hand side of `+=`");
+    }
+
+    #[test]
+    fn test_maps() {
+        assert!(matches!(eval("{}"), Object::Map(entries) if entries.is_empty()));
+        assert_eq!(eval_number("[let p = { x: 1, \"y\": 2, }; p.x + p.y]"), 3.0);
+        assert_eq!(eval_number("[let r = { inner: { v: [1, 2] } }; r.inner.v[1]]"), 2.0);
+        assert_eq!(eval_number("[let r = { a: 1 }; r.a += 4; r.b = 10; r.a + r.b]"), 15.0);
+        assert_eq!(eval_number("[let r = { v: [1, 2] }; r.v[0] = 7; r.v[0]]"), 7.0);
+        assert_eq!(eval_number("{ 1 -> 5, _ -> 6 }"), 5.0);
+        assert_eq!(eval_number("[let x = 0; { x -> 5, _ -> 6 }]"), 6.0);
+
+        let Object::Vector(keys) = eval("[let r = { b: 1, a: 2, c: 3 }; r.a = 4; r.d = 5; keys(r)]") else { panic!() };
+        let keys: Vec<String> = keys.into_iter().map(|key| if let Object::String(key) = key { key } else { panic!() }).collect();
+        assert_eq!(keys, vec!["b", "a", "c", "d"]);
+        assert!(matches!(eval("values({ a: 1, b: 2 })"), Object::Vector(values) if matches!(values[..], [Object::Number(1.0), Object::Number(2.0)])));
+        assert_eq!(eval_number("[let r = { a: 1 }; has(r, \"a\") + has(r, \"b\")]"), 1.0);
+        assert_eq!(eval_number("[let r = { a: 1, b: 2 }; r = remove(r, \"a\"); has(r, \"a\") + r.b]"), 2.0);
+        assert_eq!(eval_number("[let keys = fn m -> 42; keys({})]"), 42.0);
+
+        assert_eq!(eval_error("{ a: 1 }.b"), "Map has no field: b");
+        assert_eq!(eval_error("[let r = { a: 1 }; r.b += 1]"), "Map has no field: b");
+        assert_eq!(eval_error("[let r = { a: 1 }; r.b.c = 1]"), "Map has no field: b");
+        assert_eq!(eval_error("[1, 2].a"), "Only maps have fields.");
+        assert_eq!(eval_error("keys([1, 2])"), "keys() expects a map.");
+        assert_eq!(parse_error("{ a: 1, b 2 }").to_string(), "expected `:`, found number `2`");
+        assert_eq!(parse_error("x.[1]").to_string(), "expected identifier, found `[`");
+    }
+
+    #[test]
+    fn test_match() {
+        assert_eq!(eval_number("match 2 { 1 -> 10, 2 -> 20, _ -> 30 }"), 20.0);
+        assert_eq!(eval_number("match -1 { -1 -> 1, _ -> 2 }"), 1.0);
+        assert_eq!(eval_number("match \"b\" { \"a\" -> 1, \"b\" -> 2, }"), 2.0);
+        assert_eq!(eval_number("match 7 { n if n < 5 -> 0, n -> n * 2 }"), 14.0);
+        assert_eq!(eval_number("match [1, 2, 3] { [] -> 0, [x,] -> x, [a, b, c] -> a + b + c }"), 6.0);
+        assert_eq!(eval_number("match [[1, 2], 3] { [[a, _], b] -> a + b }"), 4.0);
+
+        let sum = "[let sum = fn v -> match v { [] -> 0, [head, ..tail] -> head + sum(tail) }; sum([1, 2, 3, 4])]";
+        assert_eq!(eval_number(sum), 10.0);
+        assert_eq!(eval_number("match [1, 2, 3, 4] { [first, .., last] -> first * 10 + last }"), 14.0);
+        assert_eq!(eval_number("match [1, 2] { [a, b, c, ..] -> 0, [a, ..rest, b] -> a + b }"), 3.0);
+        assert_eq!(eval_error("match [1, 2] { [a, ..rest, b] -> rest[0] }"), "Index 0 is out of range for a vector of length 0.");
+        assert_eq!(eval_error("[match 5 { y -> y }; y]"), "Undefined variable: y");
+        assert_eq!(eval_number("[let x = 1; match 5 { x -> x }; x]"), 1.0);
+
+        assert_eq!(eval_number("match [7,] { [x,] -> x }"), 7.0);
+        assert_eq!(eval_number("match [[1, 2],] { [[a, b],] -> a + b }"), 3.0);
+        assert_eq!(eval_number("match [1,] { [..r] -> r[0] }"), 1.0);
+        assert_eq!(eval_error("match [1] { [..r] -> r }"), "No pattern matches the value.");
+        assert_eq!(parse_error("match [1,] { [x] -> x }").to_string(), "a vector pattern with one element needs a trailing comma, as in `[x,]`");
+
+        assert_eq!(eval_error("match 3 { 1 -> 1, n if n > 5 -> 2 }"), "No pattern matches the value.");
+        assert_eq!(parse_error("match 1 { 1 + 2 -> 3 }").to_string(), "expected `if` or `->`, found `+`");
+        assert_eq!(parse_error("match 1 { [a, ..b, ..c] -> 3 }").to_string(), "a vector pattern can only have one `..` rest");
+        assert_eq!(parse_error("match 1 { (a) -> 3 }").to_string(), "expected pattern, found `(`");
+    }
+
+    #[test]
+    fn test_pipeline_and_method_calls() {
+        let code = "[let h = fn x -> x + 1; let g = fn x -> x * 2; let f = fn x -> x - 3; ";
+        assert_eq!(eval_number(&format!("{}5 |> h |> g |> f]", code)), 9.0);
+        assert_eq!(eval_number(&format!("{}f(g(h(5))) == (5 |> h |> g |> f)]", code)), 1.0);
+        assert_eq!(eval_number(&format!("{}let y = 0; y = 1 + 2 |> g; y]", code)), 6.0);
+        assert_eq!(eval_number(&format!("{}0 || 1 |> h]", code)), 2.0);
+        assert_eq!(eval_number("[let add = fn a -> fn b -> a + b; 1 |> add(10)]"), 11.0);
+
+        assert_eq!(eval_number("[let sub = fn a, b -> a - b; 10.sub(4)]"), 6.0);
+        assert_eq!(eval_number("[let twice = fn v -> v * 2; 1.5.twice()]"), 3.0);
+        assert_eq!(eval_number("[let sub = fn a, b -> a - b; [let x = 10; x.sub(1).sub(2)]]"), 7.0);
+        assert_eq!(eval_number("[let r = { a: 1, size: fn -> 42 }; r.size() + r.has(\"a\")]"), 43.0);
+        assert_eq!(eval_number("[let r = { a: 1, b: 2 }; r.remove(\"a\").values()[0]]"), 2.0);
+        assert_eq!(eval_error("5.missing()"), "Undefined variable: missing");
+        assert_eq!(eval_error("[let r = { f: 1 }; r.f()]"), "Only functions can be called.");
+    }
+
+    #[test]
+    fn test_render() {
+        let code = "[x = 1;\n\ty = (2 + 3]";
+        let expected = "error: expected `)`, found `]`\n --> 2:12\n  |\n2 | \ty = (2 + 3]\n  | \t          ^\n";
+        assert_eq!(parse_error(code).render(code), expected);
+    }
+
+    #[test]
+    fn test_end_of_input_span() {
+        let code = "(1 + \"żółw\"";
+        let error = parse_error(code);
+        assert_eq!((error.span().line, error.span().column), (1, 12));
+        assert!(error.render(code).contains(" --> 1:12\n"));
+
+        let error = parse_error("(1 + \"multi\nline\"");
+        assert_eq!((error.span().line, error.span().column), (2, 6));
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ParseError {
+    UnexpectedToken {
+        found: Token,
+        span: Span,
+        expected: Vec<&'static str>,
+    },
+    UnexpectedEof {
+        span: Span,
+        expected: Vec<&'static str>,
+    },
+    // Tokens left over after a complete program; the span covers all of them.
+    TrailingTokens {
+        found: Token,
+        span: Span,
+    },
+    // Syntactically well-formed code that is not allowed where it appears.
+    Invalid {
+        message: String,
+        span: Span,
+    },
+}
+
+impl ParseError {
+    pub fn span(&self) -> Span {
+        match self {
+            ParseError::UnexpectedToken { span, .. } => *span,
+            ParseError::UnexpectedEof { span, .. } => *span,
+            ParseError::TrailingTokens { span, .. } => *span,
+            ParseError::Invalid { span, .. } => *span,
+        }
+    }
+
+    pub fn render(&self, source: &str) -> String {
+        diagnostic::render(source, self.span(), &self.to_string())
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (expected, found) = match self {
+            ParseError::UnexpectedToken { found, expected, .. } => (expected, found.to_string()),
+            ParseError::UnexpectedEof { expected, .. } => (expected, "end of input".to_string()),
+            ParseError::TrailingTokens { found, .. } => return write!(f, "unexpected {} after the end of the program", found),
+            ParseError::Invalid { message, .. } => return write!(f, "{}", message),
+        };
+
+        write!(f, "expected ")?;
+        for (index, description) in expected.iter().enumerate() {
+            if index > 0 {
+                write!(f, "{}", if index + 1 == expected.len() { " or " } else { ", " })?;
+            }
+            write!(f, "{}", description)?;
+        }
+        write!(f, ", found {}", found)
+    }
+}
 
 pub struct Parser<'a> {
     index: usize,
     current_token: Option<&'a Token>,
-    tokens: &'a [Token],
+    tokens: &'a [SpannedToken],
+    recovering: bool,
+    errors: Vec<ParseError>,
+    allow_index: bool,
+    loop_depth: usize,
+    function_depth: usize,
+    list_separators: Vec<Token>,
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(tokens: &'a [Token]) -> Parser<'a> {
+    pub fn new(tokens: &'a [SpannedToken]) -> Parser<'a> {
         Parser {
             index: 0,
             current_token: None,
             tokens,
+            recovering: false,
+            errors: Vec::new(),
+            allow_index: true,
+            loop_depth: 0,
+            function_depth: 0,
+            list_separators: Vec::new(),
         }
     }
 
-    pub fn start_parsing(&mut self) -> Result<Expr, String> {
+    pub fn start_parsing(&mut self) -> Result<Expr, ParseError> {
         self.next_token();
         self.parse()
     }
 
-    pub fn parse(&mut self) -> Result<Expr, String> {
-        self.parse_comparison()
-    }
-
-    fn parse_comparison(&mut self) -> Result<Expr, String> {
-        let mut left = self.parse_add_sub()?;
-        while let Some(token) = self.current_token {
-            match token {
-                Token::Greater | Token::Less | Token::LessEqual | Token::GreaterEqual | Token::EqualEqual => {
-
-                    self.next_token();
+    // Parses the whole token stream as one program, rejecting anything after the first complete expression.
+    pub fn parse_program(&mut self) -> Result<Expr, ParseError> {
+        let program = self.start_parsing()?;
 
-                    let right = self.parse_add_sub()?;
-
-                    left = Expr::BinaryOperator {
-                        left: Box::new(left),
-                        right: Box::new(right),
-                        op: token.clone(),
-                    };
-                }
-                _ => break,
-            }
+        match self.trailing_tokens() {
+            Some(error) => Err(error),
+            None => Ok(program),
         }
-
-        Ok(left)
     }
-    fn parse_add_sub(&mut self) -> Result<Expr, String> {
-        
-        let mut left = self.parse_mul_div()?;
-        while let Some(token) = self.current_token {
-            match token {
-                Token::Plus | Token::Minus => {
-
-                    self.next_token();
 
-                    let right = self.parse_mul_div()?;
+    // Like parse_program, but keeps going after syntax errors: broken list items become Expr::Error nodes and
+    // every error is returned alongside the best-effort tree.
+    pub fn parse_recovering(&mut self) -> (Expr, Vec<ParseError>) {
+        self.recovering = true;
+        self.next_token();
 
-                    left = Expr::BinaryOperator {
-                        left: Box::new(left),
-                        right: Box::new(right),
-                        op: token.clone(),
-                    };
-                }
-                _ => break,
-            }
+        let program = self.parse().unwrap_or_else(|error| {
+            self.errors.push(error);
+            Expr::Error
+        });
+        if let Some(error) = self.trailing_tokens() {
+            self.errors.push(error);
         }
 
-        Ok(left)
+        (program, std::mem::take(&mut self.errors))
     }
 
-    fn parse_mul_div(&mut self) -> Result<Expr, String> {
-        
-        let mut left = self.parse_function_call()?;
-        while let Some(token) = self.current_token {
-            match token {
-                Token::Mul | Token::Div | Token::Mod => {
-
-                    self.next_token();
+    pub fn parse(&mut self) -> Result<Expr, ParseError> {
+        self.parse_expression(0)
+    }
 
-                    let right = self.parse_function_call()?;
+    // Pratt parser: keeps folding operators into `left` while they bind at least as tightly as `min_power`.
+    fn parse_expression(&mut self, min_power: u8) -> Result<Expr, ParseError> {
+        let mut left = match self.current_token {
+            Some(token) if prefix_binding_power(token).is_some() => {
+                self.next_token();
+                let expr = self.parse_expression(prefix_binding_power(token).unwrap())?;
+                Expr::UnaryOperator {
+                    op: token.clone(),
+                    expr: Box::new(expr),
+                }
+            },
+            _ => self.parse_leaf()?,
+        };
 
-                    left = Expr::BinaryOperator {
+        while let Some(token) = self.current_token {
+            if let Some(power) = postfix_binding_power(token) {
+                if power < min_power || (*token == Token::LBracket && !self.allow_index) {
+                    break;
+                }
+                left = self.parse_postfix(left)?;
+            } else if let Some((left_power, right_power)) = infix_binding_power(token) {
+                if left_power < min_power {
+                    break;
+                }
+                let span = self.current_span();
+                self.next_token();
+                let right = self.parse_expression(right_power)?;
+                left = if let Token::Equal | Token::PlusEqual | Token::MinusEqual | Token::MulEqual | Token::DivEqual | Token::ModEqual = token {
+                    let target = match to_place(left) {
+                        Some(target) => target,
+                        None => return Err(ParseError::Invalid { message: format!("invalid left-hand side of {}", token), span }),
+                    };
+                    Expr::Assign {
+                        target,
+                        op: compound_operator(token),
+                        value: Box::new(right),
+                    }
+                } else if let Token::PipeGreater = token {
+                    Expr::FunctionCall(Box::new(right), vec![left])
+                } else if let Token::DotDot | Token::DotDotEqual = token {
+                    // `step` is only a keyword right after the end of a range, so it stays usable as a name elsewhere.
+                    let step = if matches!(self.current_token, Some(Token::Identifier(name)) if name == "step") {
+                        self.next_token();
+                        Some(Box::new(self.parse_expression(right_power)?))
+                    } else {
+                        None
+                    };
+                    Expr::Range {
+                        start: Box::new(left),
+                        end: Box::new(right),
+                        step,
+                        inclusive: *token == Token::DotDotEqual,
+                    }
+                } else {
+                    Expr::BinaryOperator {
                         left: Box::new(left),
                         right: Box::new(right),
                         op: token.clone(),
-                    };
-                }
-                _ => break,
+                    }
+                };
+            } else {
+                break;
             }
         }
 
         Ok(left)
     }
 
-    fn parse_function_call(&mut self) -> Result<Expr, String> {
-        let mut left = self.parse_leaf()?;
-        while let Some(token) = self.current_token {
-            match token {
-                Token::LParen => {
-
+    fn parse_postfix(&mut self, left: Expr) -> Result<Expr, ParseError> {
+        match self.current_token {
+            Some(Token::LParen) => {
+                let args = self.parse_call_args()?;
+                Ok(Expr::FunctionCall(Box::new(left), args))
+            },
+            Some(Token::LBracket) => {
+                self.next_token();
+                let index = self.parse_with_index(true)?;
+                if let Some(Token::RBracket) = self.current_token {
                     self.next_token();
-
-
-                    if let Some(Token::RParen) = self.current_token {
-                        self.next_token();
-                        return Ok(Expr::FunctionCall(Box::new(left), Vec::new()));
-                    }
-
-                    let mut args = Vec::new();
-                    loop {
-                        let arg = self.parse()?;
-                        args.push(arg);
-
-
-                        match self.current_token {
-                            Some(Token::Comma) => {
-                                self.next_token();
-
-                            }
-                            Some(Token::RParen) => {
-                                self.next_token();
-
-                                break;
-                            }
-                            _ => {
-                                return Err("Expected ',' or ']' after an argument.".to_string());
-                            }
-                        }
+                    Ok(Expr::Index(Box::new(left), Box::new(index)))
+                } else {
+                    Err(self.unexpected(&["`]`"]))
+                }
+            },
+            Some(Token::Dot) => {
+                self.next_token();
+                if let Some(Token::Identifier(name)) = self.current_token {
+                    self.next_token();
+                    if let Some(Token::LParen) = self.current_token {
+                        let args = self.parse_call_args()?;
+                        Ok(Expr::MethodCall(Box::new(left), name.clone(), args))
+                    } else {
+                        Ok(Expr::Field(Box::new(left), name.clone()))
                     }
-
-
-                    left = Expr::FunctionCall(Box::new(left), args)
+                } else {
+                    Err(self.unexpected(&["identifier"]))
                 }
-                _ => break,
-            }
+            },
+            _ => Err(self.unexpected(&["`(`", "`[`", "`.`"])),
         }
+    }
 
-        Ok(left)
+    fn parse_call_args(&mut self) -> Result<Vec<Expr>, ParseError> {
+        self.next_token();
+        if let Some(Token::RParen) = self.current_token {
+            self.next_token();
+            Ok(Vec::new())
+        } else {
+            self.parse_list(&Token::Comma, &Token::RParen, &["`,`", "`)`"], Parser::parse, || Expr::Error)
+        }
     }
 
-    fn parse_leaf(&mut self) -> Result<Expr, String> {
+    fn parse_leaf(&mut self) -> Result<Expr, ParseError> {
         
         match self.current_token {
             Some(Token::Number(value)) => {
                 self.next_token();
                 Ok(Expr::Number(*value))
             },
+            Some(Token::Integer(value)) => {
+                self.next_token();
+                Ok(Expr::Number(*value as f64))
+            },
+            Some(Token::String(value)) => {
+                self.next_token();
+                Ok(Expr::String(value.clone()))
+            },
             Some(Token::LParen) => {
                 self.next_token();
-                let expr = self.parse()?;
+                let expr = self.parse_with_index(true)?;
                 if let Some(Token::RParen) = self.current_token {
                     self.next_token();
                     Ok(expr)
                 } else {
-                    Err("Expected right paren!".to_string())
+                    Err(self.unexpected(&["`)`"]))
                 }
             },
             Some(Token::Identifier(name)) => {
                 self.next_token();
-                return if let Some(Token::Equal) = self.current_token {
-                    self.next_token();
-                    let expr = self.parse()?;
-                    Ok(Expr::Assign(name.clone(), Box::new(expr)))
-                }  else {
-
-                    Ok(Expr::Variable(name.clone()))
-                }
+                Ok(Expr::Variable(name.clone()))
             },
             Some(Token::Function) => {
                 self.next_token();
@@ -183,74 +643,529 @@ impl<'a> Parser<'a> {
                 }
                 if let Some(Token::Arrow) = self.current_token {
                     self.next_token();
-                    let expr = self.parse()?;
-                    Ok(Expr::Function(args, Box::new(expr)))
+                    // Loops around the function definition are not visible from its body.
+                    let saved_loop_depth = std::mem::replace(&mut self.loop_depth, 0);
+                    self.function_depth += 1;
+                    let expr = self.parse();
+                    self.function_depth -= 1;
+                    self.loop_depth = saved_loop_depth;
+                    Ok(Expr::Function(args, Box::new(expr?)))
                 } else {
-                    Err("kof".to_string())
+                    Err(self.unexpected(if args.is_empty() { &["identifier", "`->`"] } else { &["`,`", "`->`"] }))
                 }
             },
             Some(Token::While) => {
                 self.next_token();
-                let condition = self.parse()?;
-                let expr = self.parse()?;
+                let condition = self.parse_with_index(false)?;
+                let expr = self.parse_loop_body()?;
                 Ok(Expr::While(Box::new(condition), Box::new(expr)))
             },
+            Some(Token::For) => {
+                self.next_token();
+                let name = if let Some(Token::Identifier(name)) = self.current_token {
+                    name.clone()
+                } else {
+                    return Err(self.unexpected(&["identifier"]));
+                };
+                self.next_token();
+
+                if self.current_token != Some(&Token::In) {
+                    return Err(self.unexpected(&["`in`"]));
+                }
+                self.next_token();
+                let iterable = self.parse_with_index(false)?;
+                let expr = self.parse_loop_body()?;
+                Ok(Expr::For(name, Box::new(iterable), Box::new(expr)))
+            },
             Some(Token::LBracket) => {
-                let mut expressions = Vec::new();
                 self.next_token();
-                loop {
-                    expressions.push(self.parse()?);
-                    if let Some(Token::SemiColon) = self.current_token {
-                        self.next_token(); // Move past the semicolon
-                    } else {
-                        break;
-                    }
+                if let Some(Token::RBracket) = self.current_token {
+                    self.next_token();
+                    return Ok(Expr::Vector(Vec::new()));
                 }
 
-                return if let Some(Token::RBracket) = self.current_token {
-                    self.next_token();
-                    Ok(Expr::CodeBlock(expressions))
+                // A comma after the first element makes this a vector literal (`[a, b]`, `[a,]`), otherwise a block.
+                let first = self.parse_list_item(Parser::parse, || Expr::Error)?;
+                if let Some(Token::Comma) = self.current_token {
+                    let elements = self.parse_list_rest(vec![first], &Token::Comma, &Token::RBracket, &["`,`", "`]`"], Parser::parse, || Expr::Error)?;
+                    Ok(Expr::Vector(elements))
                 } else {
-                    Err("Expected right bracket!".to_string())
+                    let expressions = self.parse_list_rest(vec![first], &Token::SemiColon, &Token::RBracket, &["`;`", "`]`"], Parser::parse, || Expr::Error)?;
+                    Ok(Expr::CodeBlock(expressions))
                 }
             },
             Some(Token::LBrace) => {
+                self.next_token();
+                // `{}` and braces starting with `key:` are maps, anything else is a switch.
+                match (self.current_token, self.peek_token()) {
+                    (Some(Token::RBrace), _) => {
+                        self.next_token();
+                        return Ok(Expr::Map(Vec::new()));
+                    },
+                    (Some(Token::Identifier(_) | Token::String(_)), Some(Token::Colon)) => {
+                        let entries = self.parse_list(&Token::Comma, &Token::RBrace, &["`,`", "`}`"], Parser::parse_map_entry, || (String::new(), Expr::Error))?;
+                        return Ok(Expr::Map(entries));
+                    },
+                    _ => {},
+                }
+
+                let arms = self.parse_list(&Token::Comma, &Token::RBrace, &["`,`", "`}`"], Parser::parse_switch_arm, || (Some(Expr::Error), Expr::Error))?;
+
                 let mut cases = Vec::new();
                 let mut expressions = Vec::new();
+                let mut default = None;
+                for (case, expression) in arms {
+                    match case {
+                        Some(case) => {
+                            cases.push(case);
+                            expressions.push(expression);
+                        },
+                        None => default = Some(Box::new(expression)),
+                    }
+                }
+                Ok(Expr::Switch(cases, expressions, default))
+            },
+            Some(Token::Let) => {
+                self.next_token();
+                let name = if let Some(Token::Identifier(name)) = self.current_token {
+                    name.clone()
+                } else {
+                    return Err(self.unexpected(&["identifier"]));
+                };
                 self.next_token();
 
-                loop {
-                    cases.push(self.parse()?);
-                    if let Some(Token::Arrow) = self.current_token {
-                        self.next_token();
-                        expressions.push(self.parse()?);
-                        if let Some(Token::Comma) = self.current_token {
-                            self.next_token();
-                        } else {
-                            break
-                        }
-                    } else {
-                        return Err("RJIRJ".to_string())
-                    }
+                if self.current_token != Some(&Token::Equal) {
+                    return Err(self.unexpected(&["`=`"]));
+                }
+                self.next_token();
+                Ok(Expr::Let(name, Box::new(self.parse()?)))
+            },
+            Some(Token::Break) => {
+                if self.loop_depth == 0 {
+                    return Err(self.invalid("`break` can only be used inside a loop"));
+                }
+                self.next_token();
+                Ok(Expr::Break(self.parse_jump_value()?))
+            },
+            Some(Token::Continue) => {
+                if self.loop_depth == 0 {
+                    return Err(self.invalid("`continue` can only be used inside a loop"));
+                }
+                self.next_token();
+                Ok(Expr::Continue)
+            },
+            Some(Token::Return) => {
+                if self.function_depth == 0 {
+                    return Err(self.invalid("`return` can only be used inside a function"));
+                }
+                self.next_token();
+                Ok(Expr::Return(self.parse_jump_value()?))
+            },
+            Some(Token::Match) => {
+                self.next_token();
+                let value = self.parse()?;
+                if self.current_token != Some(&Token::LBrace) {
+                    return Err(self.unexpected(&["`{`"]));
+                }
+                self.next_token();
+                let arms = self.parse_list(&Token::Comma, &Token::RBrace, &["`,`", "`}`"], Parser::parse_match_arm, || (Pattern::Wildcard, None, Expr::Error))?;
+                Ok(Expr::Match(Box::new(value), arms))
+            },
+            Some(Token::If) => {
+                self.next_token();
+                let condition = self.parse()?;
+                if let Some(Token::Then) = self.current_token {
+                    self.next_token();
+                } else {
+                    return Err(self.unexpected(&["`then`"]));
                 }
 
-                return if let Some(Token::RBrace) = self.current_token {
+                let then_expr = self.parse()?;
+                let else_expr = if let Some(Token::Else) = self.current_token {
                     self.next_token();
-                    Ok(Expr::Switch(cases, expressions))
+                    Some(Box::new(self.parse()?))
                 } else {
-                    Err("Expected right brace!".to_string())
+                    None
+                };
+                Ok(Expr::If(Box::new(condition), Box::new(then_expr), else_expr))
+            },
+            _ => Err(self.unexpected(&["expression"])),
+        }
+    }
+
+    fn parse_map_entry(&mut self) -> Result<(String, Expr), ParseError> {
+        let key = match self.current_token {
+            Some(Token::Identifier(key) | Token::String(key)) => key.clone(),
+            _ => return Err(self.unexpected(&["identifier", "string"])),
+        };
+        self.next_token();
+
+        if self.current_token != Some(&Token::Colon) {
+            return Err(self.unexpected(&["`:`"]));
+        }
+        self.next_token();
+        Ok((key, self.parse()?))
+    }
+
+    fn parse_match_arm(&mut self) -> Result<(Pattern, Option<Expr>, Expr), ParseError> {
+        let pattern = self.parse_pattern()?;
+        let guard = if let Some(Token::If) = self.current_token {
+            self.next_token();
+            Some(self.parse()?)
+        } else {
+            None
+        };
+
+        if let Some(Token::Arrow) = self.current_token {
+            self.next_token();
+            Ok((pattern, guard, self.parse()?))
+        } else {
+            Err(self.unexpected(if guard.is_some() { &["`->`"] } else { &["`if`", "`->`"] }))
+        }
+    }
+
+    fn parse_pattern(&mut self) -> Result<Pattern, ParseError> {
+        let pattern = match self.current_token {
+            Some(Token::Identifier(name)) if name == "_" => Pattern::Wildcard,
+            Some(Token::Identifier(name)) => Pattern::Binding(name.clone()),
+            Some(Token::Number(value)) => Pattern::Number(*value),
+            Some(Token::Integer(value)) => Pattern::Number(*value as f64),
+            Some(Token::String(value)) => Pattern::String(value.clone()),
+            Some(Token::Minus) => {
+                self.next_token();
+                match self.current_token {
+                    Some(Token::Number(value)) => Pattern::Number(-value),
+                    Some(Token::Integer(value)) => Pattern::Number(-(*value as f64)),
+                    _ => return Err(self.unexpected(&["number"])),
+                }
+            },
+            Some(Token::LBracket) => {
+                self.next_token();
+                if let Some(Token::RBracket) = self.current_token {
+                    self.next_token();
+                    return Ok(Pattern::Vector(Vec::new()));
+                }
+
+                let span = self.current_span();
+                let first = self.parse_list_item(Parser::parse_vector_pattern_item, || Pattern::Wildcard)?;
+                // Like the vector literal `[x,]`, a single element needs a trailing comma; `[x]` would read as a block.
+                if let (Some(Token::RBracket), false) = (self.current_token, matches!(first, Pattern::Rest(_))) {
+                    return Err(ParseError::Invalid {
+                        message: "a vector pattern with one element needs a trailing comma, as in `[x,]`".to_string(),
+                        span: self.current_span(),
+                    });
+                }
+                let items = self.parse_list_rest(vec![first], &Token::Comma, &Token::RBracket, &["`,`", "`]`"], Parser::parse_vector_pattern_item, || Pattern::Wildcard)?;
+                if items.iter().filter(|item| matches!(item, Pattern::Rest(_))).count() > 1 {
+                    return Err(ParseError::Invalid {
+                        message: "a vector pattern can only have one `..` rest".to_string(),
+                        span,
+                    });
+                }
+                return Ok(Pattern::Vector(items));
+            },
+            _ => return Err(self.unexpected(&["pattern"])),
+        };
+        self.next_token();
+        Ok(pattern)
+    }
+
+    // Items of a vector pattern can also be a rest, `..` or `..name`, that takes the elements no other item matches.
+    fn parse_vector_pattern_item(&mut self) -> Result<Pattern, ParseError> {
+        if let Some(Token::DotDot) = self.current_token {
+            self.next_token();
+            return match self.current_token {
+                Some(Token::Identifier(name)) if name != "_" => {
+                    self.next_token();
+                    Ok(Pattern::Rest(Some(name.clone())))
+                },
+                Some(Token::Identifier(_)) => {
+                    self.next_token();
+                    Ok(Pattern::Rest(None))
+                },
+                _ => Ok(Pattern::Rest(None)),
+            };
+        }
+        self.parse_pattern()
+    }
+
+    // A `None` case is the `_` default arm, which has to come last.
+    fn parse_switch_arm(&mut self) -> Result<(Option<Expr>, Expr), ParseError> {
+        if let (Some(Token::Identifier(name)), Some(Token::Arrow)) = (self.current_token, self.peek_token()) {
+            if name == "_" {
+                self.next_token();
+                self.next_token();
+                let expression = self.parse()?;
+
+                if let (Some(Token::Comma), Some(next)) = (self.current_token, self.tokens.get(self.index)) {
+                    if next.token != Token::RBrace {
+                        return Err(ParseError::Invalid {
+                            message: "the `_` arm must be the last arm of a switch".to_string(),
+                            span: next.span,
+                        });
+                    }
+                }
+                return Ok((None, expression));
+            }
+        }
+
+        let case = self.parse()?;
+        if let Some(Token::Arrow) = self.current_token {
+            self.next_token();
+            Ok((Some(case), self.parse()?))
+        } else {
+            Err(self.unexpected(&["`->`"]))
+        }
+    }
+
+    // Parses `item (separator item)* separator? closer`, with the opening token already consumed. When recovering,
+    // a broken item or a missing separator is recorded and skipped, and a missing closer ends the list quietly.
+    fn parse_list<T>(
+        &mut self,
+        separator: &Token,
+        closer: &Token,
+        expected: &[&'static str],
+        parse_item: fn(&mut Self) -> Result<T, ParseError>,
+        error_item: fn() -> T,
+    ) -> Result<Vec<T>, ParseError> {
+        let first = self.parse_list_item(parse_item, error_item)?;
+        self.parse_list_rest(vec![first], separator, closer, expected, parse_item, error_item)
+    }
+
+    // Continues a list whose leading items were already parsed, starting at the token that follows them.
+    fn parse_list_rest<T>(
+        &mut self,
+        items: Vec<T>,
+        separator: &Token,
+        closer: &Token,
+        expected: &[&'static str],
+        parse_item: fn(&mut Self) -> Result<T, ParseError>,
+        error_item: fn() -> T,
+    ) -> Result<Vec<T>, ParseError> {
+        self.list_separators.push(separator.clone());
+        let items = self.parse_list_items(items, separator, closer, expected, parse_item, error_item);
+        self.list_separators.pop();
+        items
+    }
+
+    fn parse_list_items<T>(
+        &mut self,
+        mut items: Vec<T>,
+        separator: &Token,
+        closer: &Token,
+        expected: &[&'static str],
+        parse_item: fn(&mut Self) -> Result<T, ParseError>,
+        error_item: fn() -> T,
+    ) -> Result<Vec<T>, ParseError> {
+        loop {
+            if self.current_token != Some(separator) && self.current_token != Some(closer) {
+                self.recover(self.unexpected(expected))?;
+                // A separator of another kind that no open list uses is skipped, so `[a; b, c; d]` keeps going.
+                while let Some(token @ (Token::SemiColon | Token::Comma)) = self.current_token {
+                    if token == separator || self.list_separators.contains(token) {
+                        break;
+                    }
+                    self.next_token();
+                    self.synchronize();
                 }
+                if self.current_token != Some(separator) {
+                    if self.current_token == Some(closer) {
+                        self.next_token();
+                    }
+                    return Ok(items);
+                }
+            }
+
+            if self.current_token == Some(separator) {
+                self.next_token();
+            }
+            if self.current_token == Some(closer) {
+                self.next_token();
+                return Ok(items);
+            }
+
+            items.push(self.parse_list_item(parse_item, error_item)?);
+        }
+    }
+
+    fn parse_list_item<T>(&mut self, parse_item: fn(&mut Self) -> Result<T, ParseError>, error_item: fn() -> T) -> Result<T, ParseError> {
+        let allow_index = std::mem::replace(&mut self.allow_index, true);
+        let item = parse_item(self);
+        self.allow_index = allow_index;
+
+        match item {
+            Ok(item) => Ok(item),
+            Err(error) => {
+                self.recover(error)?;
+                Ok(error_item())
+            }
+        }
+    }
+
+    fn parse_loop_body(&mut self) -> Result<Expr, ParseError> {
+        self.loop_depth += 1;
+        let expr = if let Some(Token::LBracket) = self.current_token { self.parse_loop_block() } else { self.parse() };
+        self.loop_depth -= 1;
+        expr
+    }
+
+    // A `[` body is only the bracketed block. An operator after it means the `[` was meant to index the loop head,
+    // as in `while v[0] < 3 [...]`, which would otherwise quietly parse as the head `v` and the body `[0] < 3[...]`.
+    fn parse_loop_block(&mut self) -> Result<Expr, ParseError> {
+        let span = self.current_span();
+        let block = self.parse_leaf()?;
+        match self.current_token {
+            Some(token) if infix_binding_power(token).is_some() || postfix_binding_power(token).is_some() => Err(ParseError::Invalid {
+                message: "`[` after a loop head starts the loop body; put the head in parentheses to index it".to_string(),
+                span,
+            }),
+            _ => Ok(block),
+        }
+    }
+
+    // The value of `break` and `return` is optional, so it is only parsed when something other than a closing
+    // token follows.
+    fn parse_jump_value(&mut self) -> Result<Option<Box<Expr>>, ParseError> {
+        match self.current_token {
+            None | Some(Token::SemiColon | Token::Comma | Token::RParen | Token::RBracket | Token::RBrace | Token::Else) => Ok(None),
+            _ => Ok(Some(Box::new(self.parse()?))),
+        }
+    }
+
+    // Inside delimiters `[` can always index; in the head of a `while` it has to start the body instead.
+    fn parse_with_index(&mut self, allow_index: bool) -> Result<Expr, ParseError> {
+        let saved = std::mem::replace(&mut self.allow_index, allow_index);
+        let expr = self.parse();
+        self.allow_index = saved;
+        expr
+    }
+
+    fn recover(&mut self, error: ParseError) -> Result<(), ParseError> {
+        if !self.recovering {
+            return Err(error);
+        }
+        self.errors.push(error);
+        self.synchronize();
+        Ok(())
+    }
+
+    // Skips to the next `;`, `,` or closing bracket that is not nested inside the skipped tokens.
+    fn synchronize(&mut self) {
+        let mut depth = 0;
+        while let Some(token) = self.current_token {
+            match token {
+                Token::LParen | Token::LBracket | Token::LBrace => depth += 1,
+                Token::RParen | Token::RBracket | Token::RBrace if depth > 0 => depth -= 1,
+                Token::RParen | Token::RBracket | Token::RBrace => return,
+                Token::SemiColon | Token::Comma if depth == 0 => return,
+                _ => {}
+            }
+            self.next_token();
+        }
+    }
+
+    fn trailing_tokens(&self) -> Option<ParseError> {
+        let token = self.current_token?;
+        let last = self.tokens[self.tokens.len() - 1].span;
+        let mut span = self.current_span();
+        span.end = last.end;
+        span.end_line = last.end_line;
+        span.end_column = last.end_column;
+        Some(ParseError::TrailingTokens { found: token.clone(), span })
+    }
+
+    fn unexpected(&self, expected: &[&'static str]) -> ParseError {
+        let expected = expected.to_vec();
+        match self.current_token {
+            Some(token) => ParseError::UnexpectedToken { found: token.clone(), span: self.current_span(), expected },
+            None => ParseError::UnexpectedEof { span: self.current_span(), expected },
+        }
+    }
+
+    fn invalid(&self, message: &str) -> ParseError {
+        ParseError::Invalid { message: message.to_string(), span: self.current_span() }
+    }
+
+    // At the end of input this is an empty span right after the last token.
+    fn current_span(&self) -> Span {
+        match (self.current_token, self.tokens.last()) {
+            (Some(_), _) => self.tokens[self.index - 1].span,
+            (None, Some(last)) => Span {
+                start: last.span.end,
+                end: last.span.end,
+                line: last.span.end_line,
+                column: last.span.end_column,
+                end_line: last.span.end_line,
+                end_column: last.span.end_column,
             },
-            _ => Err(format!("Unexpected token!, found {:?}", self.current_token)),
+            (None, None) => Span { start: 0, end: 0, line: 1, column: 1, end_line: 1, end_column: 1 },
         }
     }
 
+    fn peek_token(&self) -> Option<&'a Token> {
+        self.tokens.get(self.index).map(|token| &token.token)
+    }
+
     fn next_token(&mut self) {
         if self.index < self.tokens.len() {
-            self.current_token = Some(&self.tokens[self.index]);
+            self.current_token = Some(&self.tokens[self.index].token);
             self.index += 1;
         } else {
             self.current_token = None;
         }
     }
 }
+
+// Binding powers of the infix operators as (left, right). An operator is left-associative when its right power
+// is the higher one and right-associative when it is the lower one.
+fn infix_binding_power(token: &Token) -> Option<(u8, u8)> {
+    match token {
+        Token::Equal | Token::PlusEqual | Token::MinusEqual | Token::MulEqual | Token::DivEqual | Token::ModEqual => Some((2, 1)),
+        Token::PipeGreater => Some((3, 4)),
+        Token::OrOr => Some((4, 5)),
+        Token::AndAnd => Some((6, 7)),
+        Token::EqualEqual | Token::BangEqual | Token::Less | Token::Greater | Token::LessEqual | Token::GreaterEqual => Some((10, 11)),
+        Token::DotDot | Token::DotDotEqual => Some((15, 16)),
+        Token::Plus | Token::Minus => Some((20, 21)),
+        Token::Mul | Token::Div | Token::Mod => Some((30, 31)),
+        Token::Caret => Some((41, 40)),
+        _ => None,
+    }
+}
+
+// Binds looser than `^`, so `-2 ^ 2` is `-(2 ^ 2)`, but tighter than `*`.
+fn prefix_binding_power(token: &Token) -> Option<u8> {
+    match token {
+        Token::Minus | Token::Plus | Token::Bang => Some(35),
+        _ => None,
+    }
+}
+
+fn postfix_binding_power(token: &Token) -> Option<u8> {
+    match token {
+        Token::LParen | Token::LBracket | Token::Dot => Some(50),
+        _ => None,
+    }
+}
+
+// The arithmetic operator a compound assignment like `+=` applies before storing.
+fn compound_operator(token: &Token) -> Option<Token> {
+    match token {
+        Token::PlusEqual => Some(Token::Plus),
+        Token::MinusEqual => Some(Token::Minus),
+        Token::MulEqual => Some(Token::Mul),
+        Token::DivEqual => Some(Token::Div),
+        Token::ModEqual => Some(Token::Mod),
+        _ => None,
+    }
+}
+
+// Only variables and indexing or field access on them can be assigned to.
+fn to_place(expr: Expr) -> Option<Place> {
+    match expr {
+        Expr::Variable(name) => Some(Place::Variable(name)),
+        Expr::Index(target, index) => Some(Place::Index(Box::new(to_place(*target)?), index)),
+        Expr::Field(target, name) => Some(Place::Field(Box::new(to_place(*target)?), name)),
+        _ => None,
+    }
+}
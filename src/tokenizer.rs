@@ -1,11 +1,16 @@
-
+use std::fmt;
+use std::io::BufRead;
+use std::ops::Range;
+use unicode_normalization::UnicodeNormalization;
+use unicode_xid::UnicodeXID;
+use crate::diagnostic;
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    fn compare_tokens(vec1: Vec<Token>, vec2: Vec<Token>) -> bool {
-        vec1.len() == vec2.len() && vec1.iter().zip(vec2.iter()).all(|(a, b)| a == b)
+    fn compare_tokens(vec1: Vec<SpannedToken>, vec2: Vec<Token>) -> bool {
+        vec1.len() == vec2.len() && vec1.iter().zip(vec2.iter()).all(|(a, b)| &a.token == b)
     }
 
     #[test]
@@ -18,12 +23,34 @@ mod tests {
         assert!(compare_tokens(tokens, expected_tokens));
     }
 
+    #[test]
+    fn test_logical_operators() {
+        let mut tokenizer = Tokenizer::new("! != & && | || !!= |> |||>");
+        let tokens = tokenizer.tokenize().unwrap();
+        let expected_tokens = vec![
+            Token::Bang, Token::BangEqual, Token::Ampersand, Token::AndAnd, Token::Pipe, Token::OrOr,
+            Token::Bang, Token::BangEqual, Token::PipeGreater, Token::OrOr, Token::PipeGreater
+        ];
+        assert!(compare_tokens(tokens, expected_tokens));
+    }
+
+    #[test]
+    fn test_assignment_operators() {
+        let mut tokenizer = Tokenizer::new("= += -= *= /= %= -> ==");
+        let tokens = tokenizer.tokenize().unwrap();
+        let expected_tokens = vec![
+            Token::Equal, Token::PlusEqual, Token::MinusEqual, Token::MulEqual, Token::DivEqual, Token::ModEqual,
+            Token::Arrow, Token::EqualEqual
+        ];
+        assert!(compare_tokens(tokens, expected_tokens));
+    }
+
     #[test]
     fn test_numbers() {
         let mut tokenizer = Tokenizer::new("123 456.789");
         let tokens = tokenizer.tokenize().unwrap();
         let expected_tokens = vec![
-            Token::Number(123.0), Token::Number(456.789)
+            Token::Integer(123), Token::Number(456.789)
         ];
         assert!(compare_tokens(tokens, expected_tokens));
     }
@@ -40,12 +67,26 @@ mod tests {
         assert!(compare_tokens(tokens, expected_tokens));
     }
 
+    #[test]
+    fn test_unicode_identifiers() {
+        let mut tokenizer = Tokenizer::new("Δt température tempe\u{301}rature _x1");
+        let tokens = tokenizer.tokenize().unwrap();
+        let expected_tokens = vec![
+            Token::Identifier("Δt".to_string()),
+            Token::Identifier("température".to_string()),
+            Token::Identifier("température".to_string()),
+            Token::Identifier("_x1".to_string())
+        ];
+        assert!(compare_tokens(tokens, expected_tokens));
+        assert!(Tokenizer::new("\u{301}a").tokenize().is_err());
+    }
+
     #[test]
     fn test_mixed_input() {
         let mut tokenizer = Tokenizer::new("123 + abc - 45.67 * xyz / %");
         let tokens = tokenizer.tokenize().unwrap();
         let expected_tokens = vec![
-            Token::Number(123.0),
+            Token::Integer(123),
             Token::Plus,
             Token::Identifier("abc".to_string()),
             Token::Minus,
@@ -58,12 +99,232 @@ mod tests {
         assert!(compare_tokens(tokens, expected_tokens));
     }
 
+    #[test]
+    fn test_numeric_literals() {
+        let mut tokenizer = Tokenizer::new("0xFF 0b1010 0o17 1e-9 6.02E23 1_000_000 0.5 .25 7 99_999_999_999_999_999_999");
+        let tokens = tokenizer.tokenize().unwrap();
+        let expected_tokens = vec![
+            Token::Integer(255), Token::Integer(10), Token::Integer(15), Token::Number(1e-9),
+            Token::Number(6.02e23), Token::Integer(1_000_000), Token::Number(0.5), Token::Number(0.25),
+            Token::Integer(7), Token::Number(1e20)
+        ];
+        assert!(compare_tokens(tokens, expected_tokens));
+    }
+
+    #[test]
+    fn test_invalid_numeric_literals() {
+        for code in ["0x", "0b102", "0xFG", "1e", "1_", "0x1_0000_0000_0000_0000"] {
+            assert!(Tokenizer::new(code).tokenize().is_err(), "{} should not lex", code);
+        }
+    }
+
+    #[test]
+    fn test_dots() {
+        let mut tokenizer = Tokenizer::new("a.b . .5 c.d.e: x");
+        let tokens = tokenizer.tokenize().unwrap();
+        let expected_tokens = vec![
+            Token::Identifier("a".to_string()), Token::Dot, Token::Identifier("b".to_string()), Token::Dot,
+            Token::Number(0.5), Token::Identifier("c".to_string()), Token::Dot, Token::Identifier("d".to_string()),
+            Token::Dot, Token::Identifier("e".to_string()), Token::Colon, Token::Identifier("x".to_string())
+        ];
+        assert!(compare_tokens(tokens, expected_tokens));
+
+        let tokens = Tokenizer::new("10.f 1. 2.5 0..2 3._x").tokenize().unwrap();
+        let expected_tokens = vec![
+            Token::Integer(10), Token::Dot, Token::Identifier("f".to_string()), Token::Number(1.0), Token::Number(2.5),
+            Token::Integer(0), Token::DotDot, Token::Integer(2), Token::Integer(3), Token::Dot, Token::Identifier("_x".to_string())
+        ];
+        assert!(compare_tokens(tokens, expected_tokens));
+    }
+
+    #[test]
+    fn test_ranges() {
+        let mut tokenizer = Tokenizer::new("0..10 1..=n 0.5..x.. ..=");
+        let tokens = tokenizer.tokenize().unwrap();
+        let expected_tokens = vec![
+            Token::Integer(0), Token::DotDot, Token::Integer(10),
+            Token::Integer(1), Token::DotDotEqual, Token::Identifier("n".to_string()),
+            Token::Number(0.5), Token::DotDot, Token::Identifier("x".to_string()), Token::DotDot,
+            Token::DotDotEqual
+        ];
+        assert!(compare_tokens(tokens, expected_tokens));
+    }
+
     #[test]
     fn test_number_with_two_dots() {
         let mut tokenizer = Tokenizer::new("12.34.56");
         let result = tokenizer.tokenize();
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_comments() {
+        let mut tokenizer = Tokenizer::new("a // line comment\n/* block /* nested */ still comment */ b / c");
+        let tokens = tokenizer.tokenize().unwrap();
+        let expected_tokens = vec![
+            Token::Identifier("a".to_string()),
+            Token::Identifier("b".to_string()),
+            Token::Div,
+            Token::Identifier("c".to_string())
+        ];
+        assert!(compare_tokens(tokens, expected_tokens));
+    }
+
+    #[test]
+    fn test_unterminated_block_comment() {
+        let mut tokenizer = Tokenizer::new("a /* /* */");
+        let result = tokenizer.tokenize();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_strings() {
+        let mut tokenizer = Tokenizer::new(r#""hello" + "a\tb\n\"c\"\\ \u{1F40D}""#);
+        let tokens = tokenizer.tokenize().unwrap();
+        let expected_tokens = vec![
+            Token::String("hello".to_string()),
+            Token::Plus,
+            Token::String("a\tb\n\"c\"\\ \u{1F40D}".to_string())
+        ];
+        assert!(compare_tokens(tokens, expected_tokens));
+    }
+
+    #[test]
+    fn test_invalid_strings() {
+        assert!(Tokenizer::new("\"unterminated").tokenize().is_err());
+        assert!(Tokenizer::new(r#""\q""#).tokenize().is_err());
+        assert!(Tokenizer::new(r#""\u{110000}""#).tokenize().is_err());
+
+        let errors = Tokenizer::new("\"a\\").tokenize().unwrap_err();
+        assert_eq!(errors[0].message, "Unterminated string literal.");
+    }
+
+    #[test]
+    fn test_non_ascii_input() {
+        let mut tokenizer = Tokenizer::new("\"żółw\" + héllo");
+        let tokens = tokenizer.tokenize().unwrap();
+        let expected_tokens = vec![
+            Token::String("żółw".to_string()),
+            Token::Plus,
+            Token::Identifier("héllo".to_string())
+        ];
+        assert_eq!(tokens[2].span, Span { start: 12, end: 18, line: 1, column: 10, end_line: 1, end_column: 15 });
+        assert!(compare_tokens(tokens, expected_tokens));
+    }
+
+    #[test]
+    fn test_unknown_characters_are_all_reported() {
+        let mut tokenizer = Tokenizer::new("a # b\n@ 1.2.3 $");
+        let errors = tokenizer.tokenize().unwrap_err();
+        let positions: Vec<(usize, usize)> = errors.iter().map(|error| (error.span.line, error.span.column)).collect();
+        assert_eq!(positions, vec![(1, 3), (2, 1), (2, 6), (2, 9)]);
+        assert_eq!(errors[0].to_string(), "1:3: Unexpected character '#'.");
+
+        let errors = Tokenizer::new("a\0b @").tokenize().unwrap_err();
+        let messages: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+        assert_eq!(messages, vec!["1:2: Unexpected character '\\0'.", "1:5: Unexpected character '@'."]);
+        assert_eq!(Tokenizer::new("\"a\0b\" // c\0d").tokenize().unwrap()[0].token, Token::String("a\0b".to_string()));
+    }
+
+    #[test]
+    fn test_lossless_round_trip() {
+        let code = "  // header\r\n[a = 1; /* b */ b = a+2]  // done\n\t\"s\" @ 0x1G\n/* end";
+        let mut tokenizer = Tokenizer::new(code);
+        let (tokens, errors) = tokenizer.tokenize_lossless();
+        let text: String = tokens.iter().map(|token| token.full_text()).collect();
+        assert_eq!(text, code);
+        assert_eq!(errors.len(), 3);
+        assert_eq!(tokens.last().unwrap().token.token, Token::Eof);
+    }
+
+    #[test]
+    fn test_lossless_trivia_attachment() {
+        let mut tokenizer = Tokenizer::new("a /* c */ // d\n  b");
+        let (tokens, _) = tokenizer.tokenize_lossless();
+        let kinds = |trivia: &Vec<Trivia>| trivia.iter().map(|trivia| trivia.kind).collect::<Vec<_>>();
+        assert_eq!(kinds(&tokens[0].trailing_trivia), vec![
+            TriviaKind::Whitespace, TriviaKind::BlockComment, TriviaKind::Whitespace, TriviaKind::LineComment
+        ]);
+        assert_eq!(kinds(&tokens[1].leading_trivia), vec![TriviaKind::Newline, TriviaKind::Whitespace]);
+        assert_eq!(tokens[1].text, "b");
+    }
+
+    fn apply_edit(code: &str, edit: &TextEdit) -> String {
+        format!("{}{}{}", &code[..edit.start], edit.text, &code[edit.end..])
+    }
+
+    #[test]
+    fn test_retokenize_matches_full_tokenize() {
+        let code = "[a = 10;\n b = \"x\" /* c */;\n  c = 1.5 + b]";
+        let edits = vec![
+            TextEdit { start: 5, end: 7, text: "2345".to_string() },
+            TextEdit { start: 1, end: 2, text: "alpha".to_string() },
+            TextEdit { start: 0, end: 0, text: "// hi\n".to_string() },
+            TextEdit { start: 9, end: 9, text: "x = 1;\n".to_string() },
+            TextEdit { start: 19, end: 21, text: "/".to_string() },
+            TextEdit { start: 17, end: 18, text: " /* */ ".to_string() },
+            TextEdit { start: 35, end: 36, text: "".to_string() },
+            TextEdit { start: 34, end: 35, text: "e".to_string() },
+            TextEdit { start: code.len(), end: code.len(), text: " ; d".to_string() },
+        ];
+        let tokens = Tokenizer::new(code).tokenize().unwrap();
+
+        for edit in edits {
+            let new_code = apply_edit(code, &edit);
+            let (new_tokens, changed) = Tokenizer::retokenize(&new_code, &tokens, &edit).unwrap();
+            assert_eq!(new_tokens, Tokenizer::new(&new_code).tokenize().unwrap(), "after edit {:?}", edit);
+            assert!(changed.end <= new_tokens.len());
+        }
+    }
+
+    #[test]
+    fn test_retokenize_changed_range() {
+        let code = "a + bb * c";
+        let tokens = Tokenizer::new(code).tokenize().unwrap();
+        let edit = TextEdit { start: 4, end: 6, text: "xyz".to_string() };
+        let (new_tokens, changed) = Tokenizer::retokenize(&apply_edit(code, &edit), &tokens, &edit).unwrap();
+        assert_eq!(changed, 2..3);
+        assert_eq!(new_tokens[2].token, Token::Identifier("xyz".to_string()));
+        assert_eq!(new_tokens[4].span, Span { start: 10, end: 11, line: 1, column: 11, end_line: 1, end_column: 12 });
+
+        let edit = TextEdit { start: 5, end: 5, text: "\"".to_string() };
+        assert!(Tokenizer::retokenize(&apply_edit(code, &edit), &tokens, &edit).is_err());
+    }
+
+    #[test]
+    fn test_iterator() {
+        let mut tokenizer = Tokenizer::new("a @ 1");
+        assert_eq!(tokenizer.next().unwrap().unwrap().token, Token::Identifier("a".to_string()));
+        assert!(tokenizer.next().unwrap().is_err());
+        assert_eq!(tokenizer.next().unwrap().unwrap().token, Token::Integer(1));
+        assert!(tokenizer.next().is_none());
+    }
+
+    #[test]
+    fn test_reader() {
+        let code = "[x = \"multi\nline\";\n /* long\n comment */ y = 0x1F]\n";
+        let streamed: Vec<SpannedToken> = Tokenizer::from_reader(code.as_bytes()).map(|token| token.unwrap()).collect();
+        assert_eq!(streamed, Tokenizer::new(code).tokenize().unwrap());
+
+        let line = "x + 1; ".repeat(200_000);
+        let streamed: Vec<SpannedToken> = Tokenizer::from_reader(std::io::Cursor::new(line.clone())).map(|token| token.unwrap()).collect();
+        assert_eq!(streamed.len(), 800_000);
+        assert_eq!(streamed, Tokenizer::new(&line).tokenize().unwrap());
+    }
+
+    #[test]
+    fn test_spans() {
+        let mut tokenizer = Tokenizer::new("a -> 12.5\n  foo");
+        let tokens = tokenizer.tokenize().unwrap();
+        let expected_spans = vec![
+            Span { start: 0, end: 1, line: 1, column: 1, end_line: 1, end_column: 2 },
+            Span { start: 2, end: 4, line: 1, column: 3, end_line: 1, end_column: 5 },
+            Span { start: 5, end: 9, line: 1, column: 6, end_line: 1, end_column: 10 },
+            Span { start: 12, end: 15, line: 2, column: 3, end_line: 2, end_column: 6 },
+        ];
+        let spans: Vec<Span> = tokens.iter().map(|token| token.span).collect();
+        assert_eq!(spans, expected_spans);
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -73,6 +334,8 @@ pub enum Token {
     Mul,
     Div,
     Mod,
+    Caret,
+    Bang,
 
     LParen,
     RParen,
@@ -84,7 +347,13 @@ pub enum Token {
     RBracket,
 
     Equal,
+    PlusEqual, // Token for '+='
+    MinusEqual, // Token for '-='
+    MulEqual, // Token for '*='
+    DivEqual, // Token for '/='
+    ModEqual, // Token for '%='
     EqualEqual, // Token for '=='
+    BangEqual, // Token for '!='
 
     Greater,
     GreaterEqual, // Token for '>='
@@ -93,160 +362,845 @@ pub enum Token {
     LessEqual, // Token for '<='
 
     SemiColon,
+    Colon,
     Comma,
+    Dot,
     Ampersand,
+    AndAnd, // Token for '&&'
+    Pipe,
+    OrOr, // Token for '||'
+    PipeGreater, // Token for '|>'
 
     Arrow,
+    DotDot, // Token for '..'
+    DotDotEqual, // Token for '..='
 
     Number(f64),
+    Integer(i64),
+    String(String),
     Identifier(String),
     Function,
     While,
+    If,
+    Then,
+    Else,
+    Let,
+    For,
+    In,
+    Break,
+    Continue,
+    Return,
+    Match,
 
     Invalid,
+    Eof, // Only produced by tokenize_lossless, to carry the trivia at the end of the file
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol = match self {
+            Token::Plus => "+",
+            Token::Minus => "-",
+            Token::Mul => "*",
+            Token::Div => "/",
+            Token::Mod => "%",
+            Token::Caret => "^",
+            Token::Bang => "!",
+            Token::LParen => "(",
+            Token::RParen => ")",
+            Token::LBrace => "{",
+            Token::RBrace => "}",
+            Token::LBracket => "[",
+            Token::RBracket => "]",
+            Token::Equal => "=",
+            Token::PlusEqual => "+=",
+            Token::MinusEqual => "-=",
+            Token::MulEqual => "*=",
+            Token::DivEqual => "/=",
+            Token::ModEqual => "%=",
+            Token::EqualEqual => "==",
+            Token::BangEqual => "!=",
+            Token::Greater => ">",
+            Token::GreaterEqual => ">=",
+            Token::Less => "<",
+            Token::LessEqual => "<=",
+            Token::SemiColon => ";",
+            Token::Colon => ":",
+            Token::Comma => ",",
+            Token::Dot => ".",
+            Token::Ampersand => "&",
+            Token::AndAnd => "&&",
+            Token::Pipe => "|",
+            Token::OrOr => "||",
+            Token::PipeGreater => "|>",
+            Token::Arrow => "->",
+            Token::DotDot => "..",
+            Token::DotDotEqual => "..=",
+            Token::Function => "fn",
+            Token::While => "while",
+            Token::If => "if",
+            Token::Then => "then",
+            Token::Else => "else",
+            Token::Let => "let",
+            Token::For => "for",
+            Token::In => "in",
+            Token::Break => "break",
+            Token::Continue => "continue",
+            Token::Return => "return",
+            Token::Match => "match",
+            Token::Number(value) => return write!(f, "number `{}`", value),
+            Token::Integer(value) => return write!(f, "number `{}`", value),
+            Token::String(value) => return write!(f, "string {:?}", value),
+            Token::Identifier(name) => return write!(f, "identifier `{}`", name),
+            Token::Invalid => return write!(f, "invalid token"),
+            Token::Eof => return write!(f, "end of input"),
+        };
+        write!(f, "`{}`", symbol)
+    }
+}
+
+// Byte range of a token in the source, plus the line and column (both 1-based) it starts and ends at.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+
+// Replaces the bytes `start..end` of the old source with `text`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TextEdit {
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TriviaKind {
+    Whitespace,
+    Newline,
+    LineComment,
+    BlockComment,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    pub text: String,
+}
+
+// A token together with its exact source text and the trivia around it. Trailing trivia runs up to
+// (not including) the next newline; everything after that leads the following token.
+#[derive(Debug, PartialEq, Clone)]
+pub struct LosslessToken {
+    pub token: SpannedToken,
+    pub text: String,
+    pub leading_trivia: Vec<Trivia>,
+    pub trailing_trivia: Vec<Trivia>,
+}
+
+impl LosslessToken {
+    pub fn full_text(&self) -> String {
+        let mut text = String::new();
+        for trivia in &self.leading_trivia {
+            text.push_str(&trivia.text);
+        }
+        text.push_str(&self.text);
+        for trivia in &self.trailing_trivia {
+            text.push_str(&trivia.text);
+        }
+        text
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct LexError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.span.line, self.span.column, self.message)
+    }
+}
+
+impl LexError {
+    pub fn render(&self, source: &str) -> String {
+        diagnostic::render(source, self.span, &self.message)
+    }
 }
 
 pub struct Tokenizer {
-    index: usize,
     current_char: char,
-    code: String
+    code: String,
+    base: usize, // Offset of code[0] in the source; only moves when reading from a stream
+    reader: Option<Box<dyn BufRead>>,
+    position: usize,
+    line: usize,
+    column: usize,
+    errors: Vec<LexError>,
+    keep_trivia: bool,
+    trivia: Vec<Trivia>,
+    pending_token: Option<SpannedToken>,
 }
 
 impl Tokenizer{
     pub fn new(code: &str) -> Tokenizer {
         Tokenizer {
-            index: 0,
-            current_char: '\0',
-            code: code.to_string()
+            current_char: code.chars().next().unwrap_or('\0'),
+            code: code.to_string(),
+            base: 0,
+            reader: None,
+            position: 0,
+            line: 1,
+            column: 1,
+            errors: Vec::new(),
+            keep_trivia: false,
+            trivia: Vec::new(),
+            pending_token: None,
+        }
+    }
+
+    // Lexes text pulled from `reader` line by line, keeping only the part of the input that is not yet tokenized.
+    pub fn from_reader<R: BufRead + 'static>(reader: R) -> Tokenizer {
+        let mut tokenizer = Tokenizer::new("");
+        tokenizer.reader = Some(Box::new(reader));
+        tokenizer.fill();
+        tokenizer.current_char = tokenizer.code.chars().next().unwrap_or('\0');
+        tokenizer
+    }
+
+    fn starting_at(code: &str, position: usize, line: usize, column: usize) -> Tokenizer {
+        let mut tokenizer = Tokenizer::new(code);
+        tokenizer.current_char = code[position..].chars().next().unwrap_or('\0');
+        tokenizer.position = position;
+        tokenizer.line = line;
+        tokenizer.column = column;
+        tokenizer
+    }
+
+    // Updates `tokens` after `edit` was applied, producing the tokens of `code` (the edited source). Only the
+    // region around the edit is lexed again; the returned range holds the indices of the tokens that changed.
+    pub fn retokenize(code: &str, tokens: &[SpannedToken], edit: &TextEdit) -> Result<(Vec<SpannedToken>, Range<usize>), Vec<LexError>> {
+        let delta = edit.text.len() as isize - (edit.end - edit.start) as isize;
+        let new_edit_end = edit.start + edit.text.len();
+
+        // A token can depend on up to one character past its end (`1..` vs `1.5`), so restart one token earlier.
+        let first = tokens.iter().position(|token| token.span.end + 1 >= edit.start).unwrap_or(tokens.len());
+        let restart = first.saturating_sub(1);
+        let mut tokenizer = if first == 0 {
+            Tokenizer::new(code)
+        } else {
+            let span = tokens[restart].span;
+            Tokenizer::starting_at(code, span.start, span.line, span.column)
+        };
+
+        let mut result = tokens[..restart].to_vec();
+        let mut old_index = restart;
+        let mut synced = None;
+
+        while let Some(token) = tokenizer.next_token() {
+            if token.span.start >= new_edit_end {
+                let old_start = shift(token.span.start, -delta);
+                while old_index < tokens.len() && tokens[old_index].span.start < old_start {
+                    old_index += 1;
+                }
+                // Past the edit the source is unchanged, so once a token starts where an old one did the rest matches.
+                if old_index < tokens.len() && tokens[old_index].span.start == old_start {
+                    synced = Some((old_index, token.span));
+                    break;
+                }
+            }
+            result.push(token);
+        }
+
+        if !tokenizer.errors.is_empty() {
+            return Err(tokenizer.errors);
+        }
+
+        let changed_end = result.len();
+        if let Some((old_index, span)) = synced {
+            let old_span = tokens[old_index].span;
+            let line_delta = span.line as isize - old_span.line as isize;
+            let column_delta = span.column as isize - old_span.column as isize;
+
+            for token in &tokens[old_index..] {
+                let mut token = token.clone();
+                if token.span.line == old_span.line {
+                    token.span.column = shift(token.span.column, column_delta);
+                }
+                if token.span.end_line == old_span.line {
+                    token.span.end_column = shift(token.span.end_column, column_delta);
+                }
+                token.span.line = shift(token.span.line, line_delta);
+                token.span.end_line = shift(token.span.end_line, line_delta);
+                token.span.start = shift(token.span.start, delta);
+                token.span.end = shift(token.span.end, delta);
+                result.push(token);
+            }
         }
+
+        let mut changed_start = restart;
+        while changed_start < changed_end && tokens.get(changed_start) == Some(&result[changed_start]) {
+            changed_start += 1;
+        }
+
+        Ok((result, changed_start..changed_end))
     }
 
-    pub fn tokenize(&mut self) -> Result<Vec<Token>, String> {
+    pub fn tokenize(&mut self) -> Result<Vec<SpannedToken>, Vec<LexError>> {
         let mut vector = Vec::new();
+        let mut errors = Vec::new();
+
+        for token in self.by_ref() {
+            match token {
+                Ok(token) => vector.push(token),
+                Err(error) => errors.push(error),
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(vector)
+        } else {
+            Err(errors)
+        }
+    }
+
+    pub fn tokenize_lossless(&mut self) -> (Vec<LosslessToken>, Vec<LexError>) {
+        let mut tokens: Vec<LosslessToken> = Vec::new();
+        self.keep_trivia = true;
+
+        loop {
+            let next = self.next_token();
+            let mut trivia = std::mem::take(&mut self.trivia);
+
+            if let Some(previous) = tokens.last_mut() {
+                let newline = trivia.iter().position(|trivia| trivia.kind == TriviaKind::Newline).unwrap_or(trivia.len());
+                previous.trailing_trivia = trivia.drain(..newline).collect();
+            }
+
+            match next {
+                Some(token) => tokens.push(LosslessToken {
+                    text: self.text(token.span.start, token.span.end).to_string(),
+                    token,
+                    leading_trivia: trivia,
+                    trailing_trivia: Vec::new(),
+                }),
+                None => {
+                    let span = self.span_from(self.position, self.line, self.column);
+                    tokens.push(LosslessToken {
+                        token: SpannedToken { token: Token::Eof, span },
+                        text: String::new(),
+                        leading_trivia: trivia,
+                        trailing_trivia: Vec::new(),
+                    });
+                    break;
+                }
+            }
+        }
+
+        (tokens, std::mem::take(&mut self.errors))
+    }
+
+    fn next_token(&mut self) -> Option<SpannedToken> {
+        // Dropping the consumed prefix moves the rest of the buffer, so only do it once the prefix is the larger part.
+        // That keeps lexing linear even when a single buffered line is very long.
+        let consumed = self.position - self.base;
+        if self.reader.is_some() && consumed > self.code.len() - consumed {
+            self.code.drain(..consumed);
+            self.base = self.position;
+        }
+
+        self.skip_trivia();
+
+        if self.at_end() {
+            return None;
+        }
+
+        let (start, line, column) = (self.position, self.line, self.column);
+
+        let token = match self.current_char {
+            '+' | '*' | '/' | '%' if self.peek_char() == '=' => {
+                let token = match self.current_char {
+                    '+' => Token::PlusEqual,
+                    '*' => Token::MulEqual,
+                    '/' => Token::DivEqual,
+                    _ => Token::ModEqual,
+                };
+                self.next_char();
+                token
+            },
+            '+' => Token::Plus,
+            '-' => {
+                if self.peek_char() == '>' {
+                    self.next_char();
+                    Token::Arrow
+                } else if self.peek_char() == '=' {
+                    self.next_char();
+                    Token::MinusEqual
+                } else {
+                    Token::Minus
+                }
+            },
+            '*' => Token::Mul,
+            '/' => Token::Div,
+            '%' => Token::Mod,
+            '^' => Token::Caret,
+            '!' => {
+                if self.peek_char() == '=' {
+                    self.next_char();
+                    Token::BangEqual
+                } else {
+                    Token::Bang
+                }
+            },
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            '[' => Token::LBracket,
+            ']' => Token::RBracket,
+            '{' => Token::LBrace,
+            '}' => Token::RBrace,
+            ';' => Token::SemiColon,
+            '=' => {
+                if self.peek_char() == '=' {
+                    self.next_char();
+                    Token::EqualEqual
+                } else {
+                    Token::Equal
+                }
+            },
+            '.' if self.peek_char() == '.' => {
+                self.next_char();
+                if self.peek_char() == '=' {
+                    self.next_char();
+                    Token::DotDotEqual
+                } else {
+                    Token::DotDot
+                }
+            },
+            '.' if !self.peek_char().is_ascii_digit() => Token::Dot,
+            ':' => Token::Colon,
+            '"' => self.lex_string(start, line, column),
+            ',' => Token::Comma,
+            '&' => {
+                if self.peek_char() == '&' {
+                    self.next_char();
+                    Token::AndAnd
+                } else {
+                    Token::Ampersand
+                }
+            },
+            '|' => {
+                if self.peek_char() == '|' {
+                    self.next_char();
+                    Token::OrOr
+                } else if self.peek_char() == '>' {
+                    self.next_char();
+                    Token::PipeGreater
+                } else {
+                    Token::Pipe
+                }
+            },
+            '>' => {
+                if self.peek_char() == '=' {
+                    self.next_char();
+                    Token::GreaterEqual
+                } else {
+                    Token::Greater
+                }
+            },
+            '<' => {
+                if self.peek_char() == '=' {
+                    self.next_char();
+                    Token::LessEqual
+                } else {
+                    Token::Less
+                }
+            },
+            other => {
+                let token = if other.is_ascii_digit() || (other == '.' && self.peek_char().is_ascii_digit()) {
+                    self.lex_number(start, line, column)
+                } else if other.is_xid_start() || other == '_' {
+                    self.lex_identifier()
+                } else {
+                    self.next_char();
+                    self.error(format!("Unexpected character '{}'.", other.escape_debug()), self.span_from(start, line, column));
+                    Token::Invalid
+                };
+
+                return Some(SpannedToken { token, span: self.span_from(start, line, column) });
+            }
+        };
 
         self.next_char();
+        Some(SpannedToken { token, span: self.span_from(start, line, column) })
+    }
+
+    fn lex_number(&mut self, start: usize, line: usize, column: usize) -> Token {
+        if self.current_char == '0' {
+            let radix = match self.peek_char() {
+                'x' | 'X' => Some(16),
+                'o' | 'O' => Some(8),
+                'b' | 'B' => Some(2),
+                _ => None,
+            };
+            if let Some(radix) = radix {
+                return self.lex_radix_number(radix, start, line, column);
+            }
+        }
+
+        let mut is_integer = true;
+        let mut valid = true;
+
+        self.skip_digits();
+
+        // As in Rust, a '.' belongs to the number unless another '.' or a name follows, so `1.` is a float while
+        // ranges like `0..10` and calls like `10.f()` still lex.
+        let next = self.peek_char();
+        if self.current_char == '.' && next != '.' && next != '_' && !next.is_xid_start() {
+            is_integer = false;
+            self.next_char();
+            self.skip_digits();
+
+            if self.current_char == '.' && self.peek_char().is_ascii_digit() {
+                let span = Span { start: self.position, end: self.position + 1, line: self.line, column: self.column, end_line: self.line, end_column: self.column + 1 };
+                self.error("Found second dot in a number.".to_string(), span);
+                valid = false;
+                while self.current_char.is_ascii_digit() || self.current_char == '.' || self.current_char == '_' {
+                    self.next_char();
+                }
+            }
+        }
+
+        if self.current_char == 'e' || self.current_char == 'E' {
+            is_integer = false;
+            self.next_char();
+            if self.current_char == '+' || self.current_char == '-' {
+                self.next_char();
+            }
+            if self.current_char.is_ascii_digit() {
+                self.skip_digits();
+            } else if valid {
+                self.error("Missing digits in exponent.".to_string(), self.span_from(start, line, column));
+                valid = false;
+            }
+        }
+
+        if !valid {
+            return Token::Invalid;
+        }
+
+        let text = self.text(start, self.position);
+        if text.ends_with('_') {
+            self.error("Numeric literal cannot end with '_'.".to_string(), self.span_from(start, line, column));
+            return Token::Invalid;
+        }
+
+        let digits = text.replace('_', "");
+        // Decimal integers too large for an i64 fall back to a float instead of failing.
+        if is_integer {
+            if let Ok(r) = digits.parse() {
+                return Token::Integer(r);
+            }
+        }
+        match digits.parse() {
+            Ok(r) => Token::Number(r),
+            Err(e) => {
+                self.error(format!("{}.", e), self.span_from(start, line, column));
+                Token::Invalid
+            }
+        }
+    }
+
+    fn lex_radix_number(&mut self, radix: u32, start: usize, line: usize, column: usize) -> Token {
+        self.next_char();
+        self.next_char();
+        let prefix = self.text(start, self.position).to_string();
+        let digits_start = self.position;
+
+        while self.current_char.is_alphanumeric() || self.current_char == '_' {
+            self.next_char();
+        }
+
+        let text = self.text(digits_start, self.position);
+        let message = if let Some(digit) = text.chars().find(|c| *c != '_' && !c.is_digit(radix)) {
+            format!("Invalid digit '{}' in '{}' literal.", digit, prefix)
+        } else if text.replace('_', "").is_empty() {
+            format!("Missing digits after '{}'.", prefix)
+        } else if text.ends_with('_') {
+            "Numeric literal cannot end with '_'.".to_string()
+        } else {
+            match i64::from_str_radix(&text.replace('_', ""), radix) {
+                Ok(r) => return Token::Integer(r),
+                Err(_) => "Integer literal is too large.".to_string(),
+            }
+        };
+
+        self.error(message, self.span_from(start, line, column));
+        Token::Invalid
+    }
+
+    fn skip_digits(&mut self) {
+        while self.current_char.is_ascii_digit() || self.current_char == '_' {
+            self.next_char();
+        }
+    }
 
-        while self.current_char != '\0' {
-            self.skip_whitespace();
+    fn lex_identifier(&mut self) -> Token {
+        let start = self.position;
 
+        while self.current_char.is_xid_continue() {
+            self.next_char();
+        }
+
+        // Identifiers are compared in NFC so precomposed and decomposed spellings name the same variable.
+        let string: String = self.text(start, self.position).nfc().collect();
+
+        match string.as_str() {
+            "fn" => Token::Function,
+            "while" => Token::While,
+            "if" => Token::If,
+            "then" => Token::Then,
+            "else" => Token::Else,
+            "let" => Token::Let,
+            "for" => Token::For,
+            "in" => Token::In,
+            "break" => Token::Break,
+            "continue" => Token::Continue,
+            "return" => Token::Return,
+            "match" => Token::Match,
+            _ => Token::Identifier(string),
+        }
+    }
+
+    fn lex_string(&mut self, start: usize, line: usize, column: usize) -> Token {
+        let mut string = String::new();
+        let mut valid = true;
+        self.next_char();
+
+        while self.current_char != '"' {
             match self.current_char {
-                '+' => vector.push(Token::Plus),
-                '-' => {
-                    if self.peek_char() == '>' {
-                        vector.push(Token::Arrow);
-                        self.next_char();
-                    } else {
-                        vector.push(Token::Minus);
-                    }
+                _ if self.at_end() => {
+                    self.error("Unterminated string literal.".to_string(), self.span_from(start, line, column));
+                    return Token::Invalid;
                 },
-                '*' => vector.push(Token::Mul),
-                '/' => vector.push(Token::Div),
-                '%' => vector.push(Token::Mod),
-                '(' => vector.push(Token::LParen),
-                ')' => vector.push(Token::RParen),
-                '[' => vector.push(Token::LBracket),
-                ']' => vector.push(Token::RBracket),
-                '{' => vector.push(Token::LBrace),
-                '}' => vector.push(Token::RBrace),
-                ';' => vector.push(Token::SemiColon),
-                '=' => {
-                    if self.peek_char() == '=' {
-                        vector.push(Token::EqualEqual);
-                        self.next_char();
-                    } else {
-                        vector.push(Token::Equal);
+                '\\' => {
+                    let (escape_start, escape_line, escape_column) = (self.position, self.line, self.column);
+                    self.next_char();
+                    if self.at_end() {
+                        // Reported as an unterminated string by the next iteration.
+                        continue;
                     }
-                },
-                ',' => vector.push(Token::Comma),
-                '&' => vector.push(Token::Ampersand),
-                '>' => {
-                    if self.peek_char() == '=' {
-                        vector.push(Token::GreaterEqual);
-                        self.next_char();
-                    } else {
-                        vector.push(Token::Greater);
+                    let escaped = match self.current_char {
+                        'n' => Some('\n'),
+                        't' => Some('\t'),
+                        '"' => Some('"'),
+                        '\\' => Some('\\'),
+                        'u' => self.lex_unicode_escape(),
+                        _ => None,
+                    };
+                    match escaped {
+                        Some(value) => string.push(value),
+                        None => {
+                            let end = self.position + self.current_char.len_utf8();
+                            let span = Span { start: escape_start, end, line: escape_line, column: escape_column, end_line: self.line, end_column: self.column + 1 };
+                            self.error(format!("Invalid escape sequence '{}'.", self.text(escape_start, end)), span);
+                            valid = false;
+                        }
                     }
                 },
-                '<' => {
-                    if self.peek_char() == '=' {
-                        vector.push(Token::LessEqual);
+                other => string.push(other),
+            }
+            self.next_char();
+        }
+
+        if valid {
+            Token::String(string)
+        } else {
+            Token::Invalid
+        }
+    }
+
+    // Expects the cursor on the 'u' of '\u{...}' and leaves it on the closing brace.
+    fn lex_unicode_escape(&mut self) -> Option<char> {
+        if self.peek_char() != '{' {
+            return None;
+        }
+        self.next_char();
+
+        let mut hex = String::new();
+        while self.peek_char().is_ascii_hexdigit() {
+            self.next_char();
+            hex.push(self.current_char);
+        }
+
+        if self.peek_char() != '}' || hex.is_empty() || hex.len() > 6 {
+            return None;
+        }
+        self.next_char();
+
+        u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32)
+    }
+
+    fn error(&mut self, message: String, span: Span) {
+        self.errors.push(LexError { message, span });
+    }
+
+    fn span_from(&self, start: usize, line: usize, column: usize) -> Span {
+        Span { start, end: self.position, line, column, end_line: self.line, end_column: self.column }
+    }
+
+    fn skip_trivia(&mut self) {
+        loop {
+            let start = self.position;
+
+            let kind = match (self.current_char, self.peek_char()) {
+                ('\n', _) => {
+                    self.next_char();
+                    TriviaKind::Newline
+                },
+                ('\r', '\n') => {
+                    self.next_char();
+                    self.next_char();
+                    TriviaKind::Newline
+                },
+                ('/', '/') => {
+                    self.skip_line_comment();
+                    TriviaKind::LineComment
+                },
+                ('/', '*') => {
+                    self.skip_block_comment();
+                    TriviaKind::BlockComment
+                },
+                (other, _) if other.is_whitespace() => {
+                    while self.current_char.is_whitespace() && self.current_char != '\n' && !(self.current_char == '\r' && self.peek_char() == '\n') {
                         self.next_char();
-                    } else {
-                        vector.push(Token::Less);
                     }
+                    TriviaKind::Whitespace
                 },
-                other => {
-                    if other.is_digit(10) || other == '.' {
-                        let mut number_str = String::new();
-                        let mut dot = false;
-
-                        while self.current_char.is_digit(10) || self.current_char == '.' {
-                            if self.current_char == '.' {
-                                if dot {
-                                    return Err("Found second dot in a number.".to_string());
-                                } else {
-                                    dot = true;
-                                }
-                            }
-                            number_str.push(self.current_char);
-                            self.next_char();
-                        }
+                _ => return,
+            };
 
-                        let number = match number_str.parse() {
-                            Ok(r) => r,
-                            Err(e) => return Err(format!("{}", e))
-                        };
-                        vector.push(Token::Number(number));
-                        continue;
-                    } else if other.is_alphabetic() || other == '_' {
-                        let mut string = String::new();
-
-                        while self.current_char.is_alphabetic() || self.current_char.is_digit(10) || self.current_char == '_' {
-                            string.push(self.current_char);
-                            self.next_char();
-                        }
-                        match string.as_str() {
-                            "fn" => {
-                                vector.push(Token::Function);
-                                continue;
-                            },
-                            "while" => {
-                                vector.push(Token::While);
-                                continue;
-                            },
-                            _ => {
-                                vector.push(Token::Identifier(string));
-                                continue;
-                            }
-                        }
-                    }
-                }
+            if self.keep_trivia {
+                self.trivia.push(Trivia { kind, text: self.text(start, self.position).to_string() });
             }
+        }
+    }
 
+    fn skip_line_comment(&mut self) {
+        while self.current_char != '\n' && !self.at_end() {
             self.next_char();
         }
-        Ok(vector)
     }
 
-    pub fn skip_whitespace(&mut self) {
-        while self.current_char.is_whitespace() {
+    fn skip_block_comment(&mut self) {
+        let (start, line, column) = (self.position, self.line, self.column);
+        let mut depth = 0;
+
+        loop {
+            match (self.current_char, self.peek_char()) {
+                _ if self.at_end() => {
+                    self.error("Unterminated block comment.".to_string(), self.span_from(start, line, column));
+                    return;
+                },
+                ('/', '*') => {
+                    depth += 1;
+                    self.next_char();
+                },
+                ('*', '/') => {
+                    depth -= 1;
+                    self.next_char();
+                    if depth == 0 {
+                        self.next_char();
+                        return;
+                    }
+                },
+                _ => {}
+            }
             self.next_char();
         }
     }
 
+    // The end of input is found from the position, so a NUL character in the source is lexed like any other.
+    fn at_end(&self) -> bool {
+        self.position - self.base >= self.code.len()
+    }
+
     pub fn next_char(&mut self) {
-         if self.index < self.code.len() {
-            self.current_char = self.code.chars().collect::<Vec<_>>()[self.index];
-            self.index += 1;
+        if self.at_end() {
+            return;
+        }
+
+        if self.current_char == '\n' {
+            self.line += 1;
+            self.column = 1;
         } else {
-            self.current_char = '\0';
+            self.column += 1;
+        }
+
+        self.position += self.current_char.len_utf8();
+        self.fill();
+        self.current_char = self.code[self.position - self.base..].chars().next().unwrap_or('\0');
+    }
+
+    pub fn peek_char(&self) -> char {
+        if self.at_end() {
+            return '\0';
         }
+        self.code[self.position - self.base + self.current_char.len_utf8()..].chars().next().unwrap_or('\0')
     }
 
-    pub fn peek_char(&mut self) -> char {
-        if self.index < self.code.len() {
-            return self.code.chars().collect::<Vec<_>>()[self.index];
+    fn text(&self, start: usize, end: usize) -> &str {
+        &self.code[start - self.base..end - self.base]
+    }
+
+    // Keeps at least the current and the next character buffered while reading from a stream.
+    fn fill(&mut self) {
+        while let Some(reader) = self.reader.as_mut() {
+            if self.code.len() - (self.position - self.base) >= 8 {
+                break;
+            }
+
+            match reader.read_line(&mut self.code) {
+                Ok(0) => self.reader = None,
+                Ok(_) => {},
+                Err(e) => {
+                    self.reader = None;
+                    let span = Span { start: self.position, end: self.position, line: self.line, column: self.column, end_line: self.line, end_column: self.column };
+                    self.error(format!("Failed to read input: {}.", e), span);
+                }
+            }
         }
-        '\0'
     }
+}
+
+impl Iterator for Tokenizer {
+    type Item = Result<SpannedToken, LexError>;
+
+    // Errors found while lexing a token are yielded before it; invalid tokens are only reported through their errors.
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.errors.is_empty() && self.pending_token.is_none() {
+            self.pending_token = self.next_token().filter(|token| token.token != Token::Invalid);
+        }
+
+        if !self.errors.is_empty() {
+            return Some(Err(self.errors.remove(0)));
+        }
+
+        self.pending_token.take().map(Ok)
+    }
+}
+
+fn shift(value: usize, delta: isize) -> usize {
+    (value as isize + delta) as usize
 }
\ No newline at end of file